@@ -44,7 +44,15 @@ pub enum Command {
 pub struct DevCommand {}
 
 #[derive(Parser)]
-pub struct BuildCommand {}
+pub struct BuildCommand {
+    /// Also build pages marked `draft: true`
+    #[arg(long = "include-drafts")]
+    pub include_drafts: bool,
+
+    /// Publish pages scheduled up to this date instead of now, e.g. 2025-06-01
+    #[arg(long = "now", value_name = "DATE")]
+    pub now: Option<String>,
+}
 
 #[derive(Parser)]
 pub struct CleanCommand {}
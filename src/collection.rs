@@ -0,0 +1,306 @@
+use crate::config::{self, Config, HtmlPage};
+use crate::data::UsedData;
+use crate::html;
+use crate::{dom, head, markdown, metadata, route};
+use color_eyre::eyre::{Context, Result, eyre};
+use indexmap::IndexMap;
+use minijinja::Value;
+use minijinja::value::{Enumerator, Object};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The files of every pattern page, keyed by page name, so `html_pages.blog` is
+/// `collections.blog` in templates.
+#[derive(Debug, Clone, Default)]
+pub struct Collections {
+    collections: Arc<IndexMap<String, Collection>>,
+}
+
+#[derive(Debug)]
+struct Collection {
+    items: Vec<CollectionItem>,
+    /// `{ name, pages }` per tag, as templates see them
+    tags: Vec<Value>,
+    /// `{ name, pages, tags }`, as templates see it
+    value: Value,
+    /// Every source and sidecar file of the collection
+    files: BTreeSet<PathBuf>,
+}
+
+/// One file of a collection, as listed on index pages and in next/previous links.
+#[derive(Debug, Clone, Serialize)]
+pub struct CollectionItem {
+    /// Source file stem, e.g. `hello` for `src/hello.md`
+    pub name: String,
+    pub source: String,
+    /// Public URL of the page, including `base_url`
+    pub url: String,
+    pub title: String,
+    pub description: String,
+    pub date: Option<String>,
+    pub tags: Vec<String>,
+    /// Drafts are only listed in development, so templates can mark them
+    pub draft: bool,
+    /// Every field the file declares
+    pub meta: Value,
+    #[serde(skip)]
+    files: BTreeSet<PathBuf>,
+}
+
+impl Collections {
+    /// Reads the metadata of every file matched by a pattern page.
+    ///
+    /// Items are sorted by their `date` field, newest first, with undated items last. Titles
+    /// and descriptions fall back to the first `<h1>` and paragraph of the unrendered source.
+    pub async fn load(config: &Config) -> Result<Self> {
+        let mut collections = IndexMap::new();
+        for (page_name, page) in &config.html_pages {
+            if page.pattern.is_none() {
+                continue;
+            }
+            let collection = load_collection(config, page_name, page)
+                .await
+                .wrap_err_with(|| format!("Failed to load collection: {page_name}"))?;
+            collections.insert(page_name.clone(), collection);
+        }
+
+        Ok(Self {
+            collections: Arc::new(collections),
+        })
+    }
+
+    /// Returns the item published before and the item published after a source file.
+    pub fn neighbours(
+        &self,
+        page_name: &str,
+        input_file: &str,
+    ) -> (Option<&CollectionItem>, Option<&CollectionItem>) {
+        let Some(collection) = self.collections.get(page_name) else {
+            return (None, None);
+        };
+        let Some(index) = collection
+            .items
+            .iter()
+            .position(|item| item.source == input_file)
+        else {
+            return (None, None);
+        };
+
+        // Newest first, so the previous item is the next one in the list
+        let previous = collection.items.get(index + 1);
+        let next = index
+            .checked_sub(1)
+            .and_then(|index| collection.items.get(index));
+        (previous, next)
+    }
+
+    /// The items of a collection, newest first.
+    pub fn items(&self, name: &str) -> Result<&[CollectionItem]> {
+        self.get(name).map(|collection| collection.items.as_slice())
+    }
+
+    /// Returns one `{ name, slug, pages }` record per tag of a collection.
+    pub fn tag_records(&self, name: &str) -> Result<Vec<Value>> {
+        self.get(name).map(|collection| collection.tags.clone())
+    }
+
+    /// Every source and sidecar file of a collection.
+    pub fn files(&self, name: &str) -> Result<BTreeSet<PathBuf>> {
+        self.get(name).map(|collection| collection.files.clone())
+    }
+
+    fn get(&self, name: &str) -> Result<&Collection> {
+        self.collections.get(name).ok_or_else(|| {
+            eyre!("No collection '{name}', collections are the html_pages with a pattern")
+        })
+    }
+
+    /// Returns the collections as a template value that records in `used` the files of each
+    /// collection a template reads.
+    pub fn tracked(&self, used: UsedData) -> Value {
+        Value::from_object(TrackedCollections {
+            collections: self.collections.clone(),
+            used,
+        })
+    }
+}
+
+impl CollectionItem {
+    /// The source and sidecar file the item was read from.
+    pub fn files(&self) -> &BTreeSet<PathBuf> {
+        &self.files
+    }
+}
+
+async fn load_collection(config: &Config, page_name: &str, page: &HtmlPage) -> Result<Collection> {
+    let mut items = Vec::new();
+    for input_file in html::find_html_files(page_name, page).await? {
+        // Unpublished pages are left out of listings, tag pages and feeds
+        if let Some(item) = load_item(config, page, &input_file)
+            .await
+            .wrap_err_with(|| format!("Failed to read {input_file}"))?
+        {
+            items.push(item);
+        }
+    }
+
+    items.sort_by(|a, b| {
+        match (&a.date, &b.date) {
+            (Some(a), Some(b)) => b.cmp(a),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        }
+        .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let mut by_tag: BTreeMap<&str, Vec<&CollectionItem>> = BTreeMap::new();
+    for item in &items {
+        for tag in &item.tags {
+            by_tag.entry(tag).or_default().push(item);
+        }
+    }
+    // Tag pages are routed by slug, so two tags with one slug would overwrite each other
+    let mut slugs: BTreeMap<String, &str> = BTreeMap::new();
+    for tag in by_tag.keys() {
+        let slug = route::slug(tag);
+        if let Some(other) = slugs.get(&slug) {
+            return Err(eyre!(
+                "Tags '{other}' and '{tag}' have the same slug '{slug}', rename one of them"
+            ));
+        }
+        slugs.insert(slug, tag);
+    }
+    let tags: Vec<Value> = by_tag
+        .into_iter()
+        .map(|(tag, pages)| {
+            minijinja::context! {
+                name => tag,
+                slug => route::slug(tag),
+                pages => Value::from_serialize(pages),
+            }
+        })
+        .collect();
+
+    let files = items
+        .iter()
+        .flat_map(|item| item.files.iter().cloned())
+        .collect();
+    let value = minijinja::context! {
+        name => page_name,
+        pages => Value::from_serialize(&items),
+        tags => tags.clone(),
+    };
+
+    Ok(Collection {
+        items,
+        tags,
+        value,
+        files,
+    })
+}
+
+async fn load_item(
+    config: &Config,
+    page: &HtmlPage,
+    input_file: &str,
+) -> Result<Option<CollectionItem>> {
+    let content = tokio::fs::read_to_string(input_file)
+        .await
+        .wrap_err_with(|| format!("Failed to read HTML file: {}", input_file))?;
+    let mut files = BTreeSet::from([PathBuf::from(input_file)]);
+
+    let is_markdown = markdown::is_markdown(input_file);
+    let (metadata, content) = metadata::read(&content, input_file, is_markdown, &mut files)?;
+    if !metadata::is_published(config, page, &metadata, None)? {
+        return Ok(None);
+    }
+    let draft = metadata.front_matter.draft.or(page.draft).unwrap_or(false);
+    let content = if is_markdown {
+        markdown::to_html(&content)
+    } else {
+        content
+    };
+    // Parse errors are reported when the page itself is rendered
+    let (document, _) = dom::parse_document(&content);
+    let (first_heading, first_paragraph) = metadata::content_fallbacks(&document);
+
+    let page_route = route::pattern_route(page.route.as_deref(), input_file)?;
+    let page_route = if config.pretty_urls {
+        route::prettify(&page_route)
+    } else {
+        page_route
+    };
+    let name = std::path::Path::new(input_file)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or(input_file)
+        .to_string();
+
+    let date = metadata::date_field(&metadata.meta, "date");
+    if let Some(date) = &date {
+        config::validate_date(date).wrap_err("Invalid date")?;
+    }
+
+    let declared = metadata.front_matter.attributes;
+    Ok(Some(CollectionItem {
+        url: head::route_url(config, &page_route),
+        title: declared
+            .title
+            .or(first_heading)
+            .unwrap_or_else(|| name.clone()),
+        description: declared.description.or(first_paragraph).unwrap_or_default(),
+        date,
+        tags: tags(&metadata.meta),
+        draft,
+        meta: metadata.meta,
+        name,
+        source: input_file.to_string(),
+        files,
+    }))
+}
+
+/// `tags: [a, b]`, or `tags: "a, b"` as written in a `<meta>` tag.
+fn tags(meta: &Value) -> Vec<String> {
+    let Ok(tags) = meta.get_attr("tags") else {
+        return Vec::new();
+    };
+    if let Some(tags) = tags.as_str() {
+        return tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    tags.try_iter()
+        .map(|tags| {
+            tags.filter_map(|tag| tag.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The collections as seen by templates.
+#[derive(Debug)]
+struct TrackedCollections {
+    collections: Arc<IndexMap<String, Collection>>,
+    used: UsedData,
+}
+
+impl Object for TrackedCollections {
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        let collection = self.collections.get(key.as_str()?)?;
+        self.used
+            .lock()
+            .unwrap()
+            .extend(collection.files.iter().cloned());
+        Some(collection.value.clone())
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        Enumerator::Values(self.collections.keys().map(Value::from).collect())
+    }
+}
@@ -0,0 +1,200 @@
+use crate::dom;
+use crate::include;
+use crate::layout;
+use crate::prelude::*;
+use crate::template::Templates;
+use color_eyre::eyre::{Result, eyre};
+use indexmap::IndexMap;
+use kuchikiki::NodeRef;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Elements named `x-<name>` are replaced with the component in `src/components/<name>.html`.
+const COMPONENT_PREFIX: &str = "x-";
+
+const COMPONENT_DIR: &str = "src/components";
+
+/// Marks the top-level elements of a component with scoped styles, and the styles themselves.
+const SCOPE_ATTRIBUTE: &str = "data-component";
+
+/// Expands the components used by one page and collects their styles.
+pub struct Components<'a> {
+    input_file: &'a str,
+    /// One stylesheet per component, in the order they were first used
+    styles: IndexMap<String, String>,
+}
+
+impl<'a> Components<'a> {
+    pub fn new(input_file: &'a str) -> Self {
+        Self {
+            input_file,
+            styles: IndexMap::new(),
+        }
+    }
+
+    /// Replaces every `<x-name>` element below `root` with its component.
+    ///
+    /// The component file is rendered as a template with the element's attributes as `props`,
+    /// or only has its `{{ props.name }}` filled in when the page is not templated. Then its
+    /// `<slot>`s are filled with the element's content the same way layout slots are.
+    /// Elements without a component file are left alone, so they can still be custom elements
+    /// defined in JavaScript. Every component read is added to `dependencies`.
+    pub fn expand(
+        &mut self,
+        templates: &Templates,
+        root: &NodeRef,
+        dependencies: &mut BTreeSet<PathBuf>,
+    ) -> Result<()> {
+        self.expand_below(templates, root, dependencies, &mut Vec::new())
+    }
+
+    fn expand_below(
+        &mut self,
+        templates: &Templates,
+        root: &NodeRef,
+        dependencies: &mut BTreeSet<PathBuf>,
+        stack: &mut Vec<String>,
+    ) -> Result<()> {
+        // Only the outermost elements, nested ones are expanded along with them
+        let elements: Vec<NodeRef> = root
+            .descendants()
+            .filter(|node| component_name(node).is_some())
+            .filter(|node| {
+                !node
+                    .ancestors()
+                    .take_while(|ancestor| ancestor != root)
+                    .any(|ancestor| component_name(&ancestor).is_some())
+            })
+            .collect();
+
+        for element in elements {
+            // Components in the content passed to this one belong to the caller
+            self.expand_below(templates, &element, dependencies, stack)?;
+
+            let name = component_name(&element).unwrap_or_default().to_string();
+            let path = Path::new(COMPONENT_DIR).join(format!("{name}.html"));
+            if !path.is_file() {
+                debug!(
+                    "{}: no {} for <{}{}>, leaving it as is",
+                    self.input_file,
+                    path.display(),
+                    COMPONENT_PREFIX,
+                    name
+                );
+                continue;
+            }
+
+            if stack.contains(&name) {
+                let chain: Vec<&str> = stack
+                    .iter()
+                    .chain(std::iter::once(&name))
+                    .map(String::as_str)
+                    .collect();
+                return Err(eyre!("Component cycle: {}", chain.join(" -> ")));
+            }
+
+            let source = std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("Failed to read component: {}", path.display()))?;
+            dependencies.insert(path.clone());
+
+            let props: BTreeMap<String, String> = element
+                .as_element()
+                .map(|element| {
+                    element
+                        .attributes
+                        .borrow()
+                        .map
+                        .iter()
+                        .map(|(name, attribute)| (name.local.to_string(), attribute.value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let component_name = path.display().to_string();
+            let content = templates.render_with_props(&component_name, &source, &props)?;
+
+            let (fragment, errors) = dom::parse_fragment_at(&element, &content);
+            for error in errors {
+                warn!("{}: {}", component_name, error);
+            }
+            include::expand_includes(templates, &fragment, &path, dependencies)
+                .wrap_err_with(|| format!("Failed to expand includes in {component_name}"))?;
+
+            stack.push(name.clone());
+            self.expand_below(templates, &fragment, dependencies, stack)?;
+            stack.pop();
+
+            self.take_styles(&name, &fragment);
+
+            let fills = layout::take_slot_fills(&element);
+            for slot in layout::fill_slots(&fragment, fills).keys() {
+                warn!(
+                    "{}: component <{}{}> has no slot named '{}'",
+                    self.input_file, COMPONENT_PREFIX, name, slot
+                );
+            }
+
+            dom::replace_with_children(&element, &fragment);
+        }
+
+        Ok(())
+    }
+
+    /// Removes the `<style>` elements of a component, keeping the first copy of its styles.
+    ///
+    /// `<style scoped>` only applies inside the component: its top-level elements are marked
+    /// with `data-component` and the rules are wrapped in an `@scope` block that stops at
+    /// nested components.
+    fn take_styles(&mut self, name: &str, fragment: &NodeRef) {
+        let styles: Vec<NodeRef> = fragment
+            .descendants()
+            .filter(|node| dom::element_name(node) == Some("style"))
+            .collect();
+        if styles.is_empty() {
+            return;
+        }
+
+        let mut css = String::new();
+        let mut scoped = false;
+        for style in styles {
+            let text = style.text_contents();
+            if dom::attribute(&style, "scoped").is_some() {
+                scoped = true;
+                css.push_str(&format!(
+                    "@scope ([{SCOPE_ATTRIBUTE}=\"{name}\"]) to ([{SCOPE_ATTRIBUTE}]) {{{text}}}\n"
+                ));
+            } else {
+                css.push_str(&text);
+                css.push('\n');
+            }
+            style.detach();
+        }
+
+        if scoped {
+            for child in fragment.children() {
+                if let Some(element) = child.as_element() {
+                    element
+                        .attributes
+                        .borrow_mut()
+                        .insert(SCOPE_ATTRIBUTE, name.to_string());
+                }
+            }
+        }
+
+        self.styles.entry(name.to_string()).or_insert(css);
+    }
+
+    /// Appends the styles of every expanded component to `head`.
+    pub fn insert_styles(self, head: &NodeRef) {
+        for (name, css) in self.styles {
+            let markup = format!("<style {SCOPE_ATTRIBUTE}=\"{name}\">{css}</style>");
+            dom::append_markup(head, &markup);
+        }
+    }
+}
+
+/// Returns `card` for an `<x-card>` element.
+fn component_name(node: &NodeRef) -> Option<&str> {
+    dom::element_name(node)?
+        .strip_prefix(COMPONENT_PREFIX)
+        .filter(|name| !name.is_empty())
+}
@@ -1,9 +1,13 @@
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, Result, eyre};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::time::{Duration, SystemTime};
 use tokio::fs;
 
+use crate::collection::Collections;
+use crate::data::DataTree;
 use crate::info;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,10 +16,14 @@ pub struct Config {
     pub default_page_attributes: PageAttributes,
 
     #[serde(default = "default_pages")]
-    pub spa_pages: HashMap<String, SpaPage>,
+    pub spa_pages: IndexMap<String, SpaPage>,
 
     #[serde(default = "default_html_pages")]
-    pub html_pages: HashMap<String, HtmlPage>,
+    pub html_pages: IndexMap<String, HtmlPage>,
+
+    /// Shared attributes that pages join with `group: "<name>"`
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub groups: IndexMap<String, PageAttributes>,
 
     #[serde(default)]
     pub watch_patterns: Vec<String>,
@@ -23,62 +31,333 @@ pub struct Config {
     #[serde(default)]
     pub env_files: EnvFiles,
 
+    /// Sorted so `window.ENV` is emitted in the same order on every build
     #[serde(skip)]
-    pub env: HashMap<String, String>,
+    pub env: BTreeMap<String, String>,
 
     #[serde(default)]
     pub pre_hook: Vec<String>,
 
     #[serde(default = "default_output_dir")]
     pub output_dir: String,
+
+    /// Public path the site is served from, e.g. `/apps/dashboard/`
+    #[serde(default = "default_base_url")]
+    pub base_url: String,
+
+    /// Emit `about.html` as `about/index.html` so pages are served without extension
+    #[serde(default)]
+    pub pretty_urls: bool,
+
+    /// Directory of JSON, JSON5, TOML, YAML and CSV files exposed to templates as `data`
+    #[serde(default = "default_data_dir")]
+    pub data_dir: String,
+
+    #[serde(skip)]
+    pub data: DataTree,
+
+    /// The files of every pattern page with their metadata, exposed to templates as
+    /// `collections`
+    #[serde(skip)]
+    pub collections: Collections,
+
+    /// Whether drafts and scheduled pages are built
+    #[serde(skip)]
+    pub publishing: Publishing,
+
+    /// Build-time highlighting of `<pre><code class="language-x">` blocks
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+
+    /// Heading ids, anchor links and the table of contents of HTML pages
+    #[serde(default)]
+    pub headings: HeadingsConfig,
+
+    /// Scheme and host the site is served from, e.g. `https://example.com`, for the absolute
+    /// URLs of feeds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site_url: Option<String>,
+
+    /// RSS and Atom feeds of collections
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feeds: Vec<Feed>,
+
+    /// `sitemap.xml` of every published page, written when `site_url` is set
+    #[serde(default)]
+    pub sitemap: SitemapConfig,
+
+    /// Rules of the generated `robots.txt`, none is written without them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub robots: Option<RobotsConfig>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            default_page_attributes: PageAttributes::default(),
+            default_page_attributes: PageAttributes::builtin(),
             spa_pages: default_pages(),
             html_pages: default_html_pages(),
+            groups: IndexMap::new(),
             env_files: EnvFiles::default(),
-            env: HashMap::new(),
+            env: BTreeMap::new(),
             pre_hook: Vec::new(),
             output_dir: default_output_dir(),
+            base_url: default_base_url(),
+            pretty_urls: false,
+            data_dir: default_data_dir(),
+            data: DataTree::default(),
+            collections: Collections::default(),
+            publishing: Publishing::default(),
+            highlight: HighlightConfig::default(),
+            headings: HeadingsConfig::default(),
+            site_url: None,
+            feeds: Vec::new(),
+            sitemap: SitemapConfig::default(),
+            robots: None,
             watch_patterns: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Attributes set on one layer of the config: the global defaults, a group or a page.
+///
+/// Every field is optional so that omitting it inherits the value from the layer below.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PageAttributes {
-    #[serde(default = "default_title")]
-    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
 
-    #[serde(default = "default_favicon")]
-    pub favicon: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<String>,
 
-    #[serde(default = "default_author")]
-    pub author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
 
-    #[serde(default = "default_description")]
-    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 
-    #[serde(default)]
-    pub scripts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scripts: Option<Vec<String>>,
 
-    #[serde(default)]
-    pub styles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub styles: Option<Vec<String>>,
+
+    /// `lang` of the `<html>` element
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+
+    /// Text direction of the `<html>` element, `ltr` or `rtl`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+
+    /// Classes of the `<html>` element, e.g. `"dark"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<ClassList>,
+
+    /// Any other `<html>` attributes, e.g. `{ "data-theme": "dark" }`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_attributes: Option<IndexMap<String, String>>,
+
+    /// How each field combines with the layer below, e.g. `merge: { scripts: "replace" }`
+    #[serde(default, skip_serializing_if = "MergeModes::is_default")]
+    pub merge: MergeModes,
 }
 
-impl Default for PageAttributes {
-    fn default() -> Self {
+impl PageAttributes {
+    /// Built-in values that sit below the global defaults.
+    pub fn builtin() -> Self {
         Self {
-            title: default_title(),
-            favicon: default_favicon(),
-            author: default_author(),
-            description: default_description(),
-            scripts: Vec::new(),
-            styles: Vec::new(),
+            title: Some(default_title()),
+            favicon: Some(default_favicon()),
+            author: Some(default_author()),
+            description: Some(default_description()),
+            scripts: Some(Vec::new()),
+            styles: Some(Vec::new()),
+            lang: Some(default_lang()),
+            dir: None,
+            class: None,
+            html_attributes: None,
+            merge: MergeModes::default(),
+        }
+    }
+
+    /// Layers `other` on top of these attributes, using the merge modes declared by `other`.
+    fn merged_with(&self, other: &PageAttributes) -> PageAttributes {
+        let modes = &other.merge;
+        PageAttributes {
+            title: merge_field(modes.title, MergeMode::Inherit, &self.title, &other.title),
+            favicon: merge_field(
+                modes.favicon,
+                MergeMode::Inherit,
+                &self.favicon,
+                &other.favicon,
+            ),
+            author: merge_field(
+                modes.author,
+                MergeMode::Inherit,
+                &self.author,
+                &other.author,
+            ),
+            description: merge_field(
+                modes.description,
+                MergeMode::Inherit,
+                &self.description,
+                &other.description,
+            ),
+            scripts: merge_field(
+                modes.scripts,
+                MergeMode::Append,
+                &self.scripts,
+                &other.scripts,
+            ),
+            styles: merge_field(modes.styles, MergeMode::Append, &self.styles, &other.styles),
+            lang: merge_field(modes.lang, MergeMode::Inherit, &self.lang, &other.lang),
+            dir: merge_field(modes.dir, MergeMode::Inherit, &self.dir, &other.dir),
+            class: merge_field(modes.class, MergeMode::Append, &self.class, &other.class),
+            html_attributes: merge_field(
+                modes.html_attributes,
+                MergeMode::Append,
+                &self.html_attributes,
+                &other.html_attributes,
+            ),
+            merge: MergeModes::default(),
+        }
+    }
+
+    fn into_resolved(self) -> ResolvedAttributes {
+        ResolvedAttributes {
+            title: self.title.unwrap_or_default(),
+            favicon: self.favicon.unwrap_or_default(),
+            author: self.author.unwrap_or_default(),
+            description: self.description.unwrap_or_default(),
+            scripts: self.scripts.unwrap_or_default(),
+            styles: self.styles.unwrap_or_default(),
+            lang: self.lang.unwrap_or_default(),
+            dir: self.dir.unwrap_or_default(),
+            class: self.class.map(|class| class.0).unwrap_or_default(),
+            html_attributes: self.html_attributes.unwrap_or_default(),
+        }
+    }
+}
+
+/// The attributes a page is rendered with, after every layer has been merged.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResolvedAttributes {
+    pub title: String,
+    pub favicon: String,
+    pub author: String,
+    pub description: String,
+    pub scripts: Vec<String>,
+    pub styles: Vec<String>,
+    pub lang: String,
+    pub dir: String,
+    pub class: String,
+    pub html_attributes: IndexMap<String, String>,
+}
+
+/// Space separated class names. Appending joins them with a space.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClassList(pub String);
+
+/// How a field combines with the value inherited from the layer below.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// Use this layer's value if set, otherwise the inherited one
+    Inherit,
+    /// Inherited value followed by this layer's value
+    Append,
+    /// This layer's value followed by the inherited value
+    Prepend,
+    /// Only this layer's value, dropping the inherited one even if this layer sets nothing
+    Replace,
+}
+
+/// Per-field merge modes. Strings default to `inherit`, lists, classes and maps to `append`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeModes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scripts: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub styles: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<MergeMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_attributes: Option<MergeMode>,
+}
+
+impl MergeModes {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Values that `append` and `prepend` can join.
+trait Concat: Clone {
+    fn concat(self, other: Self) -> Self;
+}
+
+impl Concat for String {
+    fn concat(self, other: Self) -> Self {
+        self + &other
+    }
+}
+
+impl Concat for Vec<String> {
+    fn concat(mut self, other: Self) -> Self {
+        self.extend(other);
+        self
+    }
+}
+
+impl Concat for ClassList {
+    fn concat(self, other: Self) -> Self {
+        let classes: Vec<&str> = self
+            .0
+            .split_whitespace()
+            .chain(other.0.split_whitespace())
+            .collect();
+        ClassList(classes.join(" "))
+    }
+}
+
+/// Later keys override earlier ones, keeping the position of the first occurrence.
+impl Concat for IndexMap<String, String> {
+    fn concat(mut self, other: Self) -> Self {
+        self.extend(other);
+        self
+    }
+}
+
+fn merge_field<T: Concat>(
+    mode: Option<MergeMode>,
+    default_mode: MergeMode,
+    inherited: &Option<T>,
+    own: &Option<T>,
+) -> Option<T> {
+    match (mode.unwrap_or(default_mode), inherited, own) {
+        (MergeMode::Replace, _, own) => own.clone(),
+        (MergeMode::Append, Some(inherited), Some(own)) => {
+            Some(inherited.clone().concat(own.clone()))
+        }
+        (MergeMode::Prepend, Some(inherited), Some(own)) => {
+            Some(own.clone().concat(inherited.clone()))
         }
+        (_, inherited, own) => own.clone().or_else(|| inherited.clone()),
     }
 }
 
@@ -86,6 +365,43 @@ impl Default for PageAttributes {
 pub struct HtmlPage {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pattern: Option<String>,
+    /// Where the page is written, e.g. `/docs/getting-started/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Layout the page is rendered into, relative to `src/`, e.g. `layouts/base.html`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    /// Source file relative to `src/`, instead of `<page name>.html`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Data file with a list of records, one page is generated per record, e.g.
+    /// `data/products.json` with `route: "/products/{slug}/"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_data: Option<String>,
+    /// Collection whose tags each get a page, e.g. `blog` with `route: "/blog/tags/{slug}/"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags_of: Option<String>,
+    /// Collection listed over numbered pages, e.g. `{ collection: "blog", per_page: 10 }`
+    /// writes `/blog/`, `/blog/page/2/`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paginate: Option<Pagination>,
+    /// Evaluate `{{ ... }}` and `{% ... %}` in the page's source files and the layouts, partials
+    /// and components they use. Off by default, so markup meant for client-side frameworks
+    /// passes through; on for `from_data`, `tags_of` and `paginate` pages, which show their
+    /// records through templates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templating: Option<bool>,
+    /// Only built in development, or with `bageri build --include-drafts`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<bool>,
+    /// Production builds skip the page before this date, e.g. `2025-06-01` or
+    /// `2025-06-01T09:00:00Z`, in UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_date: Option<String>,
+    #[serde(flatten)]
+    pub sitemap: SitemapSettings,
     #[serde(flatten)]
     pub attributes: PageAttributes,
 }
@@ -94,19 +410,104 @@ impl HtmlPage {
     pub fn get_source_files(&self, page_name: &str) -> String {
         if let Some(pattern) = &self.pattern {
             pattern.clone()
+        } else if let Some(template) = &self.template {
+            template.clone()
         } else {
             // Use page name as filename if no pattern specified
             format!("{}.html", page_name)
         }
     }
+
+    /// Returns true if the page's sources, layouts, partials and components are rendered as
+    /// templates.
+    pub fn is_templated(&self) -> bool {
+        self.templating.unwrap_or(
+            self.from_data.is_some() || self.tags_of.is_some() || self.paginate.is_some(),
+        )
+    }
+
+    /// The collection a tag or paginated page lists, whose files it is rebuilt for.
+    pub fn listed_collection(&self) -> Option<&str> {
+        self.tags_of.as_deref().or(self
+            .paginate
+            .as_ref()
+            .map(|paginate| paginate.collection.as_str()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pagination {
+    pub collection: String,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SpaPage {
+    /// Where the page is written, e.g. `/app/`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Layout whose `main` slot receives the app container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    #[serde(flatten)]
+    pub sitemap: SitemapSettings,
     #[serde(flatten)]
     pub attributes: PageAttributes,
 }
 
+/// How a page is listed in `sitemap.xml`, set on a page or in a source file's metadata.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SitemapSettings {
+    /// Left out of the sitemap and marked `<meta name="robots" content="noindex">`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub noindex: Option<bool>,
+    /// From 0.0 to 1.0, relative to the site's other pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changefreq: Option<ChangeFrequency>,
+}
+
+impl SitemapSettings {
+    /// These settings with the ones `other` sets on top.
+    pub fn overridden_by(&self, other: &SitemapSettings) -> SitemapSettings {
+        SitemapSettings {
+            noindex: other.noindex.or(self.noindex),
+            priority: other.priority.or(self.priority),
+            changefreq: other.changefreq.or(self.changefreq),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeFrequency {
+    Always,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Never,
+}
+
+impl ChangeFrequency {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Hourly => "hourly",
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::Monthly => "monthly",
+            Self::Yearly => "yearly",
+            Self::Never => "never",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct EnvFiles {
     #[serde(default)]
@@ -115,6 +516,185 @@ pub struct EnvFiles {
     pub prd: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HighlightConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// One of syntect's bundled themes, e.g. `base16-ocean.dark`, or a path to a `.tmTheme`
+    #[serde(default = "default_highlight_theme")]
+    pub theme: String,
+    #[serde(default)]
+    pub mode: HighlightMode,
+    /// Where the theme's stylesheet is written in the output directory, in `classes` mode
+    #[serde(default = "default_highlight_stylesheet")]
+    pub stylesheet: String,
+    /// Number the lines of every block, not only the ones that ask for it
+    #[serde(default)]
+    pub line_numbers: bool,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            theme: default_highlight_theme(),
+            mode: HighlightMode::default(),
+            stylesheet: default_highlight_stylesheet(),
+            line_numbers: false,
+        }
+    }
+}
+
+/// How highlighted code is colored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMode {
+    /// CSS classes, colored by the emitted stylesheet
+    #[default]
+    Classes,
+    /// `style` attributes, no stylesheet needed
+    Inline,
+}
+
+/// A feed of the newest items of a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feed {
+    /// Name of the pattern page whose files are the entries
+    pub collection: String,
+    /// Where the feed is written in the output directory, e.g. `feed.xml`
+    pub path: String,
+    #[serde(default)]
+    pub format: FeedFormat,
+    /// Defaults to the site's title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Most entries in the feed
+    #[serde(default = "default_feed_limit")]
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    #[default]
+    Atom,
+    Rss,
+}
+
+impl FeedFormat {
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Atom => "application/atom+xml",
+            Self::Rss => "application/rss+xml",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SitemapConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Where the sitemap, or the sitemap index of a site with more than 50 000 pages, is
+    /// written in the output directory
+    #[serde(default = "default_sitemap_path")]
+    pub path: String,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: default_sitemap_path(),
+        }
+    }
+}
+
+/// `robots.txt` groups, e.g. `{ rules: [{ user_agent: "*", disallow: ["/drafts/"] }] }`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RobotsConfig {
+    /// Allows every crawler everything when empty
+    #[serde(default)]
+    pub rules: Vec<RobotsRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RobotsRule {
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// Path prefixes, without `base_url`
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub disallow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeadingsConfig {
+    /// Append a link to each heading's own id
+    #[serde(default)]
+    pub anchors: bool,
+    /// Content of the anchor links
+    #[serde(default = "default_anchor_text")]
+    pub anchor_text: String,
+    /// Shallowest heading level in the table of contents
+    #[serde(default = "default_toc_min_level")]
+    pub toc_min_level: u8,
+    /// Deepest heading level in the table of contents
+    #[serde(default = "default_toc_max_level")]
+    pub toc_max_level: u8,
+}
+
+impl Default for HeadingsConfig {
+    fn default() -> Self {
+        Self {
+            anchors: false,
+            anchor_text: default_anchor_text(),
+            toc_min_level: default_toc_min_level(),
+            toc_max_level: default_toc_max_level(),
+        }
+    }
+}
+
+fn default_anchor_text() -> String {
+    "#".to_string()
+}
+
+fn default_toc_min_level() -> u8 {
+    2
+}
+
+fn default_toc_max_level() -> u8 {
+    3
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+fn default_sitemap_path() -> String {
+    "sitemap.xml".to_string()
+}
+
+fn default_user_agent() -> String {
+    "*".to_string()
+}
+
+fn default_feed_limit() -> usize {
+    20
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_highlight_theme() -> String {
+    "InspiredGitHub".to_string()
+}
+
+fn default_highlight_stylesheet() -> String {
+    "highlight.css".to_string()
+}
+
 fn default_title() -> String {
     "Bageri App".to_string()
 }
@@ -127,31 +707,50 @@ fn default_description() -> String {
     "Description".to_string()
 }
 
+fn default_lang() -> String {
+    "en".to_string()
+}
+
 fn default_favicon() -> String {
     "favicon.ico".to_string()
 }
 
-fn default_pages() -> HashMap<String, SpaPage> {
-    let mut pages = HashMap::new();
+fn default_pages() -> IndexMap<String, SpaPage> {
+    let mut pages = IndexMap::new();
     pages.insert(
         "index".to_string(),
         SpaPage {
+            route: None,
+            group: None,
+            layout: None,
+            sitemap: SitemapSettings::default(),
             attributes: PageAttributes::default(),
         },
     );
     pages
 }
 
-fn default_html_pages() -> HashMap<String, HtmlPage> {
-    let mut pages = HashMap::new();
+fn default_html_pages() -> IndexMap<String, HtmlPage> {
+    let mut pages = IndexMap::new();
 
     // Example without pattern - looks for src/about.html
     pages.insert(
         "about".to_string(),
         HtmlPage {
             pattern: None, // Uses key name "about" -> src/about.html
+            route: None,   // Written to dist/about.html
+            group: None,
+            layout: None,
+            template: None,
+            from_data: None,
+            tags_of: None,
+            paginate: None,
+            templating: None,
+            draft: None,
+            publish_date: None,
+            sitemap: SitemapSettings::default(),
             attributes: PageAttributes {
-                title: "About Us".to_string(), // This is the HTML <title> tag
+                title: Some("About Us".to_string()), // This is the HTML <title> tag
                 ..PageAttributes::default()
             },
         },
@@ -162,8 +761,19 @@ fn default_html_pages() -> HashMap<String, HtmlPage> {
         "blog_posts".to_string(),
         HtmlPage {
             pattern: Some("blog-*.html".to_string()),
+            route: None, // Keeps the source filename, e.g. dist/blog-hello.html
+            group: None,
+            layout: None,
+            template: None,
+            from_data: None,
+            tags_of: None,
+            paginate: None,
+            templating: None,
+            draft: None,
+            publish_date: None,
+            sitemap: SitemapSettings::default(),
             attributes: PageAttributes {
-                title: "Blog Post".to_string(), // This is the HTML <title> tag
+                title: Some("Blog Post".to_string()), // This is the HTML <title> tag
                 ..PageAttributes::default()
             },
         },
@@ -176,26 +786,272 @@ fn default_output_dir() -> String {
     "dist".to_string()
 }
 
+fn default_data_dir() -> String {
+    "data".to_string()
+}
+
+fn default_base_url() -> String {
+    "/".to_string()
+}
+
+/// Timestamp from `SOURCE_DATE_EPOCH`, used in place of the current time so two builds from
+/// the same inputs produce identical output.
+pub fn source_date_epoch() -> Option<SystemTime> {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+    let seconds = seconds.trim().parse::<u64>().ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Overrides of what a build publishes, from the command line.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Build drafts in production too
+    pub include_drafts: bool,
+    /// Publish pages scheduled up to this date instead of the current time
+    pub now: Option<String>,
+}
+
+/// Decides which drafts and scheduled pages a build publishes.
+#[derive(Debug, Clone, Default)]
+pub struct Publishing {
+    pub include_drafts: bool,
+    pub include_scheduled: bool,
+    /// `YYYY-MM-DDTHH:MM:SSZ`, or the `--now` date as given
+    pub now: String,
+}
+
+impl Publishing {
+    /// Development builds everything. Production builds skip drafts and pages with a
+    /// `publish_date` after now, unless `options` say otherwise.
+    pub fn new(env: Env, options: &BuildOptions) -> Result<Self> {
+        let now = match &options.now {
+            Some(now) => {
+                validate_date(now).wrap_err("Invalid --now")?;
+                now.clone()
+            }
+            None => utc_timestamp(source_date_epoch().unwrap_or_else(SystemTime::now)),
+        };
+        let development = matches!(env, Env::Development);
+        Ok(Self {
+            include_drafts: development || options.include_drafts,
+            include_scheduled: development,
+            now,
+        })
+    }
+
+    /// Returns true if a page with these settings is built.
+    ///
+    /// A date without a time is published from the start of that day, in UTC unless the
+    /// date has an offset.
+    pub fn is_published(&self, draft: bool, publish_date: Option<&str>) -> Result<bool> {
+        if draft && !self.include_drafts {
+            return Ok(false);
+        }
+        match publish_date {
+            Some(date) => {
+                let date = parse_date(date)?;
+                Ok(self.include_scheduled || date <= parse_date(&self.now)?)
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+/// Checks that a date is in a form [`parse_date`] accepts.
+pub fn validate_date(date: &str) -> Result<()> {
+    parse_date(date).map(|_| ())
+}
+
+/// Returns the seconds since 1970-01-01 UTC of `YYYY-MM-DD`, optionally followed by a time
+/// (`T09:30`, `T09:30:00`, `T09:30:00.5`, with `T` or a space) and `Z` or a `+02:00` offset.
+/// A date without a time is the start of that day, and a time without an offset is UTC.
+pub fn parse_date(date: &str) -> Result<i64> {
+    parse_timestamp(date).ok_or_else(|| {
+        eyre!(
+            "Date '{date}' must be YYYY-MM-DD, optionally followed by a time such as \
+             T09:30:00Z or T09:30:00+02:00"
+        )
+    })
+}
+
+fn parse_timestamp(date: &str) -> Option<i64> {
+    if date.get(4..5)? != "-" || date.get(7..8)? != "-" {
+        return None;
+    }
+    let (year, month, day) = (
+        digits(date, 0..4)?,
+        digits(date, 5..7)?,
+        digits(date, 8..10)?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+
+    let Some(time) = date.get(10..).filter(|time| !time.is_empty()) else {
+        return Some(days * 86_400);
+    };
+    let time = time.strip_prefix(['T', ' '])?;
+    if time.get(2..3)? != ":" {
+        return None;
+    }
+    let (hour, minute) = (digits(time, 0..2)?, digits(time, 3..5)?);
+    let mut second = 0;
+    let mut rest = &time[5..];
+    if let Some(seconds) = rest.strip_prefix(':') {
+        second = digits(seconds, 0..2)?;
+        rest = &seconds[2..];
+        if let Some(fraction) = rest.strip_prefix('.') {
+            rest = fraction.trim_start_matches(|c: char| c.is_ascii_digit());
+            if rest.len() == fraction.len() {
+                return None;
+            }
+        }
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let offset = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.get(0..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.get(3..4)? != ":" {
+                return None;
+            }
+            sign * (digits(rest, 1..3)? * 3_600 + digits(rest, 4..6)? * 60)
+        }
+    };
+    Some(days * 86_400 + hour * 3_600 + minute * 60 + second - offset)
+}
+
+/// Parses the ASCII digits at `range` of `text`.
+fn digits(text: &str, range: std::ops::Range<usize>) -> Option<i64> {
+    let digits = text.get(range)?;
+    if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Formats a time as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60
+    )
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Env {
     Development,
     Production,
 }
 
+impl Env {
+    /// Name of the environment as exposed to templates.
+    pub fn name(self) -> &'static str {
+        match self {
+            Env::Development => "development",
+            Env::Production => "production",
+        }
+    }
+}
+
 impl Config {
+    /// Resolves the attributes a page is rendered with.
+    ///
+    /// Precedence is the built-in values, the global defaults, then the page's group, then the
+    /// page itself. Each layer combines with the one below according to its merge modes.
+    pub fn resolve_attributes(
+        &self,
+        group: Option<&str>,
+        page: &PageAttributes,
+    ) -> ResolvedAttributes {
+        self.resolve_source_attributes(group, page, &PageAttributes::default())
+    }
+
+    /// Like [`Config::resolve_attributes`], with the attributes a source file declares itself,
+    /// e.g. in front matter, layered on top of the page.
+    pub fn resolve_source_attributes(
+        &self,
+        group: Option<&str>,
+        page: &PageAttributes,
+        source: &PageAttributes,
+    ) -> ResolvedAttributes {
+        let mut attributes = PageAttributes::builtin().merged_with(&self.default_page_attributes);
+        if let Some(group) = group.and_then(|name| self.groups.get(name)) {
+            attributes = attributes.merged_with(group);
+        }
+        attributes
+            .merged_with(page)
+            .merged_with(source)
+            .into_resolved()
+    }
+
     pub async fn load(env: Option<Env>) -> Result<Self> {
-        Self::load_from("bageri.json5", env).await
+        Self::load_from("bageri.json5", env, &BuildOptions::default()).await
+    }
+
+    /// Like [`Config::load`], with the command line overrides of a build.
+    pub async fn load_with(env: Option<Env>, options: &BuildOptions) -> Result<Self> {
+        Self::load_from("bageri.json5", env, options).await
     }
 
-    pub async fn load_from<P: AsRef<Path>>(path: P, env: Option<Env>) -> Result<Self> {
+    pub async fn load_from<P: AsRef<Path>>(
+        path: P,
+        env: Option<Env>,
+        options: &BuildOptions,
+    ) -> Result<Self> {
         let content = fs::read_to_string(&path)
             .await
             .with_context(|| format!("Failed to read config file: {}", path.as_ref().display()))?;
 
         let mut config: Config = serde_json5::from_str(&content)
             .with_context(|| format!("Failed to parse config file: {}", path.as_ref().display()))?;
+        config.validate_groups()?;
 
-        config.env = HashMap::new();
+        config.env = BTreeMap::new();
+        config.base_url = normalize_base_url(&config.base_url);
 
         let env = match env {
             Some(e) => e,
@@ -204,6 +1060,7 @@ impl Config {
                 _ => Env::Development,
             },
         };
+        config.publishing = Publishing::new(env, options)?;
         // Load environment variables from specified env files
         let env_file = match env {
             Env::Production => config.env_files.prd.as_deref().unwrap_or(".env.prd"),
@@ -225,6 +1082,145 @@ impl Config {
             }
         }
 
+        // Scripts need the public path to build asset and API URLs at runtime
+        config
+            .env
+            .insert("BASE_URL".to_string(), config.base_url.clone());
+
         Ok(config)
     }
+
+    /// Reads the data files and collections pages are built from. Kept out of
+    /// [`Config::load`], so commands that only need the config, like `clean`, work whatever
+    /// state the sources are in.
+    pub async fn load_sources(&mut self) -> Result<()> {
+        self.data = DataTree::load(Path::new(&self.data_dir))?;
+        self.collections = Collections::load(self).await?;
+        for feed in &self.feeds {
+            self.collections
+                .items(&feed.collection)
+                .wrap_err_with(|| format!("Invalid feed: {}", feed.path))?;
+            if self.site_url.is_none() {
+                return Err(eyre!(
+                    "Feed {} needs a site_url, e.g. \"https://example.com\", to link to its pages",
+                    feed.path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_groups(&self) -> Result<()> {
+        let page_groups = self
+            .spa_pages
+            .iter()
+            .map(|(name, page)| (name, &page.group))
+            .chain(
+                self.html_pages
+                    .iter()
+                    .map(|(name, page)| (name, &page.group)),
+            );
+
+        for (page_name, group) in page_groups {
+            if let Some(group) = group
+                && !self.groups.contains_key(group)
+            {
+                return Err(eyre!("Page '{page_name}' uses undefined group '{group}'"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Ensures the base URL starts and ends with `/`, so asset paths can be appended directly.
+fn normalize_base_url(base_url: &str) -> String {
+    let trimmed = base_url.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{trimmed}/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dates_and_times_as_utc() {
+        assert_eq!(parse_date("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_date("2025-06-01").unwrap(), 1_748_736_000);
+        assert_eq!(parse_date("2025-06-01T09:30").unwrap(), 1_748_770_200);
+        assert_eq!(parse_date("2025-06-01 09:30:00").unwrap(), 1_748_770_200);
+        assert_eq!(parse_date("2025-06-01T09:30:00Z").unwrap(), 1_748_770_200);
+        assert_eq!(
+            parse_date("2025-06-01T09:30:00.250Z").unwrap(),
+            1_748_770_200
+        );
+    }
+
+    #[test]
+    fn applies_offsets() {
+        assert_eq!(
+            parse_date("2025-06-01T11:30:00+02:00").unwrap(),
+            parse_date("2025-06-01T09:30:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_date("2025-06-01T00:30-01:00").unwrap(),
+            parse_date("2025-06-01T01:30Z").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_dates() {
+        for date in [
+            "",
+            "2025-6-1",
+            "2025-13-01",
+            "2025-06-01x",
+            "2025-06-01T9:30",
+            "2025-06-01T09:30Zjunk",
+            "2025-06-01T09:30+0200",
+            "2025-06-01T09:30:00.",
+            "2025-06-01T24:00",
+            "２０２５-06-01",
+        ] {
+            assert!(parse_date(date).is_err(), "{date} should be rejected");
+        }
+    }
+
+    #[test]
+    fn compares_publish_dates_with_offsets_as_instants() {
+        let publishing = Publishing {
+            now: "2025-06-01T10:00:00Z".to_string(),
+            ..Publishing::default()
+        };
+        // 11:30 in UTC+2 is 09:30 UTC, before now
+        assert!(
+            publishing
+                .is_published(false, Some("2025-06-01T11:30:00+02:00"))
+                .unwrap()
+        );
+        // 09:30 in UTC-1 is 10:30 UTC, after now
+        assert!(
+            !publishing
+                .is_published(false, Some("2025-06-01T09:30:00-01:00"))
+                .unwrap()
+        );
+        assert!(publishing.is_published(false, Some("2025-06-01")).unwrap());
+        assert!(publishing.is_published(false, Some("June 1st")).is_err());
+    }
+
+    #[test]
+    fn counts_days_from_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        assert_eq!(
+            utc_timestamp(SystemTime::UNIX_EPOCH + Duration::from_secs(19_782 * 86_400)),
+            "2024-02-29T00:00:00Z"
+        );
+    }
 }
@@ -0,0 +1,167 @@
+use crate::prelude::*;
+use color_eyre::eyre::{Result, eyre};
+use minijinja::Value;
+use minijinja::value::{Enumerator, Object};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The files of the data directory, keyed by file stem and nested by subdirectory, so
+/// `data/team.yaml` is `data.team` and `data/blog/authors.json` is `data.blog.authors`.
+#[derive(Debug, Clone, Default)]
+pub struct DataTree {
+    root: Arc<DataDir>,
+}
+
+#[derive(Debug, Default)]
+struct DataDir {
+    entries: BTreeMap<String, DataEntry>,
+}
+
+#[derive(Debug)]
+enum DataEntry {
+    Dir(Arc<DataDir>),
+    File { path: PathBuf, value: Value },
+}
+
+/// The data files a page read while it was rendered.
+pub type UsedData = Arc<Mutex<BTreeSet<PathBuf>>>;
+
+impl DataTree {
+    /// Loads every JSON, JSON5, TOML, YAML and CSV file below `dir`. A missing directory gives
+    /// an empty tree.
+    pub fn load(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            return Ok(Self::default());
+        }
+
+        Ok(Self {
+            root: Arc::new(load_dir(dir)?),
+        })
+    }
+
+    /// Returns the contents of a data file by its path, e.g. `data/products.json`.
+    pub fn file(&self, path: &Path) -> Option<&Value> {
+        let path = path.strip_prefix(".").unwrap_or(path);
+        find_file(&self.root, path)
+    }
+
+    /// Returns the tree as a template value that records each file a template reads in `used`.
+    pub fn tracked(&self, used: UsedData) -> Value {
+        Value::from_object(TrackedDir {
+            dir: self.root.clone(),
+            used,
+        })
+    }
+}
+
+fn load_dir(dir: &Path) -> Result<DataDir> {
+    let mut paths = std::fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read data directory: {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .wrap_err("Failed to read directory entry")?;
+    paths.sort();
+
+    let mut entries = BTreeMap::new();
+    for path in paths {
+        let Some(key) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if key.starts_with('.') {
+            continue;
+        }
+
+        let entry = if path.is_dir() {
+            DataEntry::Dir(Arc::new(load_dir(&path)?))
+        } else {
+            let Some(value) = load_file(&path)? else {
+                debug!("Skipping data file with unknown format: {}", path.display());
+                continue;
+            };
+            DataEntry::File {
+                path: path.clone(),
+                value,
+            }
+        };
+
+        if entries.insert(key.to_string(), entry).is_some() {
+            return Err(eyre!(
+                "More than one data entry is named '{}' in {}",
+                key,
+                dir.display()
+            ));
+        }
+    }
+
+    Ok(DataDir { entries })
+}
+
+fn find_file<'a>(dir: &'a DataDir, path: &Path) -> Option<&'a Value> {
+    dir.entries.values().find_map(|entry| match entry {
+        DataEntry::Dir(dir) => find_file(dir, path),
+        DataEntry::File { path: file, value } => (file == path).then_some(value),
+    })
+}
+
+/// Parses a data file by its extension, or returns `None` for formats that are not data.
+pub fn load_file(path: &Path) -> Result<Option<Value>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    if !matches!(
+        extension,
+        "json" | "json5" | "toml" | "yaml" | "yml" | "csv"
+    ) {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read data file: {}", path.display()))?;
+    let parse_error = || format!("Failed to parse data file: {}", path.display());
+
+    let value = match extension {
+        // JSON5 is a superset of JSON
+        "json" | "json5" => serde_json5::from_str(&content).wrap_err_with(parse_error)?,
+        "toml" => toml::from_str(&content).wrap_err_with(parse_error)?,
+        "yaml" | "yml" => serde_yaml::from_str(&content).wrap_err_with(parse_error)?,
+        _ => parse_csv(&content).wrap_err_with(parse_error)?,
+    };
+    Ok(Some(value))
+}
+
+/// A CSV file is a list of records keyed by the header row. Values stay strings.
+fn parse_csv(content: &str) -> Result<Value> {
+    let mut reader = csv::Reader::from_reader(content.as_bytes());
+    let records = reader
+        .deserialize::<BTreeMap<String, String>>()
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(Value::from_serialize(records))
+}
+
+/// A data directory as seen by templates.
+#[derive(Debug)]
+struct TrackedDir {
+    dir: Arc<DataDir>,
+    used: UsedData,
+}
+
+impl Object for TrackedDir {
+    fn get_value(self: &Arc<Self>, key: &Value) -> Option<Value> {
+        match self.dir.entries.get(key.as_str()?)? {
+            DataEntry::Dir(dir) => Some(Value::from_object(TrackedDir {
+                dir: dir.clone(),
+                used: self.used.clone(),
+            })),
+            DataEntry::File { path, value } => {
+                self.used.lock().unwrap().insert(path.clone());
+                Some(value.clone())
+            }
+        }
+    }
+
+    fn enumerate(self: &Arc<Self>) -> Enumerator {
+        Enumerator::Values(self.dir.entries.keys().map(Value::from).collect())
+    }
+}
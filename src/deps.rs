@@ -0,0 +1,70 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// One source file of an HTML page, the unit that can be rebuilt on its own.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PageSource {
+    pub page_name: String,
+    pub input_file: String,
+}
+
+/// Which pages read which files, so a change only rebuilds the pages that depend on it.
+#[derive(Debug, Default)]
+pub struct DependencyGraph {
+    dependents: BTreeMap<PathBuf, BTreeSet<PageSource>>,
+    /// Files read by SPA pages, which are only built along with the whole site
+    site_wide: BTreeSet<PathBuf>,
+}
+
+impl DependencyGraph {
+    /// Records the files a page read while it was built.
+    pub fn record(&mut self, page: &PageSource, dependencies: impl IntoIterator<Item = PathBuf>) {
+        for dependency in dependencies {
+            self.dependents
+                .entry(canonical(&dependency))
+                .or_default()
+                .insert(page.clone());
+        }
+    }
+
+    /// Records the files an SPA page read, a change to any of them rebuilds everything.
+    pub fn record_site_wide(&mut self, dependencies: impl IntoIterator<Item = PathBuf>) {
+        self.site_wide
+            .extend(dependencies.into_iter().map(|path| canonical(&path)));
+    }
+
+    /// Returns the pages to rebuild for a set of changed files, or `None` if any of them is not
+    /// a known page dependency, or is read by an SPA page, and everything needs to be rebuilt.
+    pub fn affected_pages(&self, changed: &[PathBuf]) -> Option<BTreeSet<PageSource>> {
+        if changed.is_empty() {
+            return None;
+        }
+
+        let mut pages = BTreeSet::new();
+        for path in changed {
+            if self.site_wide.contains(&canonical(path)) {
+                return None;
+            }
+            pages.extend(self.dependents.get(&canonical(path))?.iter().cloned());
+        }
+        Some(pages)
+    }
+
+    /// Replaces what is known about `pages` with the dependencies recorded by a partial rebuild.
+    pub fn update(&mut self, pages: &BTreeSet<PageSource>, rebuilt: DependencyGraph) {
+        for dependents in self.dependents.values_mut() {
+            dependents.retain(|page| !pages.contains(page));
+        }
+        self.dependents
+            .retain(|_, dependents| !dependents.is_empty());
+
+        for (path, dependents) in rebuilt.dependents {
+            self.dependents.entry(path).or_default().extend(dependents);
+        }
+    }
+}
+
+/// Deleted files cannot be canonicalized, they are kept as reported.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
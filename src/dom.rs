@@ -0,0 +1,250 @@
+use html5ever::tendril::{StrTendril, TendrilSink};
+use html5ever::tokenizer::TokenizerOpts;
+use html5ever::tree_builder::{ElementFlags, NodeOrText, QuirksMode, TreeBuilderOpts, TreeSink};
+use html5ever::{Attribute, ExpandedName, LocalName, ParseOpts, QualName, namespace_url, ns};
+use kuchikiki::NodeRef;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+
+/// A non-fatal problem the HTML5 parser recovered from.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: u64,
+    pub message: Cow<'static, str>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses a full HTML document the way a browser would, collecting parse errors with the line
+/// they occurred on.
+///
+/// A missing doctype is not reported, since source pages are usually body fragments.
+pub fn parse_document(source: &str) -> (NodeRef, Vec<ParseError>) {
+    let (document, errors) = html5ever::parse_document(new_sink(), parse_opts()).one(source);
+    let errors = errors
+        .into_iter()
+        .filter(|error| !error.message.ends_with("insertion mode Initial"))
+        .collect();
+    (document, errors)
+}
+
+/// Parses markup as if it were the contents of a `context` element, e.g. `head` or `body`.
+///
+/// Returns a container element whose children are the parsed nodes.
+pub fn parse_fragment(source: &str, context: &str) -> (NodeRef, Vec<ParseError>) {
+    let context = QualName::new(None, ns!(html), LocalName::from(context));
+    let (document, errors) =
+        html5ever::parse_fragment(new_sink(), parse_opts(), context, Vec::new()).one(source);
+    let container = document.first_child().unwrap_or(document);
+    (container, errors)
+}
+
+/// Parses markup that is to take the place of `node`, in the context of `node`'s parent.
+pub fn parse_fragment_at(node: &NodeRef, source: &str) -> (NodeRef, Vec<ParseError>) {
+    let context = node
+        .parent()
+        .and_then(|parent| element_name(&parent).map(str::to_string))
+        .unwrap_or_else(|| "body".to_string());
+    parse_fragment(source, &context)
+}
+
+/// Puts the children of `fragment` where `node` is and removes `node`.
+pub fn replace_with_children(node: &NodeRef, fragment: &NodeRef) {
+    for child in fragment.children().collect::<Vec<_>>() {
+        node.insert_before(child);
+    }
+    node.detach();
+}
+
+/// Replaces `node` with parsed markup, returning the errors the parser recovered from.
+pub fn replace_with_markup(node: &NodeRef, source: &str) -> Vec<ParseError> {
+    let (fragment, errors) = parse_fragment_at(node, source);
+    replace_with_children(node, &fragment);
+    errors
+}
+
+/// Parses markup as contents of the `parent` element and appends it.
+pub fn append_markup(parent: &NodeRef, source: &str) {
+    let context = element_name(parent).unwrap_or("body");
+    let (fragment, _) = parse_fragment(source, context);
+    for child in fragment.children().collect::<Vec<_>>() {
+        parent.append(child);
+    }
+}
+
+fn new_sink() -> LineTrackingSink {
+    LineTrackingSink {
+        inner: kuchikiki::Sink::default(),
+        line: Cell::new(1),
+        errors: RefCell::new(Vec::new()),
+    }
+}
+
+fn parse_opts() -> ParseOpts {
+    ParseOpts {
+        tokenizer: TokenizerOpts {
+            exact_errors: true,
+            ..Default::default()
+        },
+        tree_builder: TreeBuilderOpts {
+            exact_errors: true,
+            ..Default::default()
+        },
+    }
+}
+
+/// Returns the local name of an element node, e.g. `meta`.
+pub fn element_name(node: &NodeRef) -> Option<&str> {
+    node.as_element().map(|element| &*element.name.local)
+}
+
+/// Returns the value of an attribute on an element node.
+pub fn attribute(node: &NodeRef, name: &str) -> Option<String> {
+    let element = node.as_element()?;
+    element.attributes.borrow().get(name).map(str::to_string)
+}
+
+/// Removes an attribute from an element node, returning its value.
+pub fn take_attribute(node: &NodeRef, name: &str) -> Option<String> {
+    let element = node.as_element()?;
+    element
+        .attributes
+        .borrow_mut()
+        .remove(name)
+        .map(|attribute| attribute.value)
+}
+
+/// Serializes the children of a node, without the node itself.
+pub fn inner_html(node: &NodeRef) -> String {
+    node.children().map(|child| child.to_string()).collect()
+}
+
+/// Joins two space separated class lists, dropping classes already present.
+pub fn union_classes(existing: &str, added: &str) -> String {
+    let mut classes: Vec<&str> = existing.split_whitespace().collect();
+    for class in added.split_whitespace() {
+        if !classes.contains(&class) {
+            classes.push(class);
+        }
+    }
+    classes.join(" ")
+}
+
+/// Returns the first element matching `selector` below `node`.
+pub fn select_first(node: &NodeRef, selector: &str) -> Option<NodeRef> {
+    node.select_first(selector)
+        .ok()
+        .map(|e| e.as_node().clone())
+}
+
+/// Wraps kuchikiki's tree sink to remember the line each parse error was reported on.
+struct LineTrackingSink {
+    inner: kuchikiki::Sink,
+    line: Cell<u64>,
+    errors: RefCell<Vec<ParseError>>,
+}
+
+impl TreeSink for LineTrackingSink {
+    type Handle = NodeRef;
+    type Output = (NodeRef, Vec<ParseError>);
+    type ElemName<'a> = ExpandedName<'a>;
+
+    fn finish(self) -> Self::Output {
+        (self.inner.document_node, self.errors.into_inner())
+    }
+
+    fn parse_error(&self, message: Cow<'static, str>) {
+        self.errors.borrow_mut().push(ParseError {
+            line: self.line.get(),
+            message,
+        });
+    }
+
+    fn set_current_line(&self, line_number: u64) {
+        self.line.set(line_number);
+    }
+
+    fn get_document(&self) -> NodeRef {
+        self.inner.get_document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a NodeRef) -> ExpandedName<'a> {
+        self.inner.elem_name(target)
+    }
+
+    fn create_element(
+        &self,
+        name: QualName,
+        attrs: Vec<Attribute>,
+        flags: ElementFlags,
+    ) -> NodeRef {
+        self.inner.create_element(name, attrs, flags)
+    }
+
+    fn create_comment(&self, text: StrTendril) -> NodeRef {
+        self.inner.create_comment(text)
+    }
+
+    fn create_pi(&self, target: StrTendril, data: StrTendril) -> NodeRef {
+        self.inner.create_pi(target, data)
+    }
+
+    fn append(&self, parent: &NodeRef, child: NodeOrText<NodeRef>) {
+        self.inner.append(parent, child)
+    }
+
+    fn append_based_on_parent_node(
+        &self,
+        element: &NodeRef,
+        prev_element: &NodeRef,
+        child: NodeOrText<NodeRef>,
+    ) {
+        self.inner
+            .append_based_on_parent_node(element, prev_element, child)
+    }
+
+    fn append_doctype_to_document(
+        &self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril,
+    ) {
+        self.inner
+            .append_doctype_to_document(name, public_id, system_id)
+    }
+
+    /// Keeps template contents as ordinary children, so they are serialized and can be
+    /// searched like the rest of the tree.
+    fn get_template_contents(&self, target: &NodeRef) -> NodeRef {
+        target.clone()
+    }
+
+    fn same_node(&self, x: &NodeRef, y: &NodeRef) -> bool {
+        self.inner.same_node(x, y)
+    }
+
+    fn set_quirks_mode(&self, mode: QuirksMode) {
+        self.inner.set_quirks_mode(mode)
+    }
+
+    fn append_before_sibling(&self, sibling: &NodeRef, new_node: NodeOrText<NodeRef>) {
+        self.inner.append_before_sibling(sibling, new_node)
+    }
+
+    fn add_attrs_if_missing(&self, target: &NodeRef, attrs: Vec<Attribute>) {
+        self.inner.add_attrs_if_missing(target, attrs)
+    }
+
+    fn remove_from_parent(&self, target: &NodeRef) {
+        self.inner.remove_from_parent(target)
+    }
+
+    fn reparent_children(&self, node: &NodeRef, new_parent: &NodeRef) {
+        self.inner.reparent_children(node, new_parent)
+    }
+}
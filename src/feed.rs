@@ -0,0 +1,329 @@
+use crate::collection::CollectionItem;
+use crate::config::{self, Config, Feed, FeedFormat, HtmlPage};
+use crate::dom;
+use crate::head;
+use crate::xml::escape;
+use color_eyre::eyre::{Result, eyre};
+use maud::{Markup, html};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Renders a feed of a collection's newest items.
+///
+/// `contents` holds the rendered content of each page by source file, without its layout.
+/// Links in the content are made absolute with `site_url`, since feed readers show it outside
+/// the site.
+pub fn render(config: &Config, feed: &Feed, contents: &BTreeMap<String, String>) -> Result<String> {
+    let site_url = config
+        .site_url
+        .as_deref()
+        .ok_or_else(|| eyre!("Feed {} needs a site_url", feed.path))?
+        .trim_end_matches('/');
+
+    // Drafts are only built in development, and a feed should not announce them
+    let items: Vec<&CollectionItem> = config
+        .collections
+        .items(&feed.collection)?
+        .iter()
+        .filter(|item| !item.draft)
+        .take(feed.limit)
+        .collect();
+    let title = title(config, feed);
+    // Atom needs an author, the site stands in for one
+    let author = config
+        .default_page_attributes
+        .author
+        .clone()
+        .unwrap_or_else(|| title.clone());
+    let entries: Vec<Entry> = items
+        .iter()
+        .map(|item| Entry {
+            item,
+            url: format!("{site_url}{}", item.url),
+            content: contents
+                .get(&item.source)
+                .map(|content| absolute_links(content, site_url, &item.url)),
+        })
+        .collect();
+
+    let home = format!("{site_url}{}", config.base_url);
+    let feed_url = format!("{site_url}{}", head::route_url(config, &feed.path));
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    match feed.format {
+        FeedFormat::Atom => {
+            let updated = entries
+                .iter()
+                .find_map(|entry| entry.item.date.as_deref())
+                .map(atom_date)
+                .unwrap_or_else(|| match config::source_date_epoch() {
+                    Some(timestamp) => config::utc_timestamp(timestamp),
+                    None => atom_date(&config.publishing.now),
+                });
+            writeln!(xml, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+            writeln!(xml, "  <title>{}</title>", escape(&title))?;
+            writeln!(xml, r#"  <link href="{}"/>"#, escape(&home))?;
+            writeln!(xml, r#"  <link rel="self" href="{}"/>"#, escape(&feed_url))?;
+            writeln!(xml, "  <id>{}</id>", escape(&feed_url))?;
+            writeln!(xml, "  <updated>{}</updated>", escape(&updated))?;
+            writeln!(xml, "  <author><name>{}</name></author>", escape(&author))?;
+            for entry in &entries {
+                let date = entry.item.date.as_deref().map(atom_date);
+                writeln!(xml, "  <entry>")?;
+                writeln!(xml, "    <title>{}</title>", escape(&entry.item.title))?;
+                writeln!(xml, r#"    <link href="{}"/>"#, escape(&entry.url))?;
+                writeln!(xml, "    <id>{}</id>", escape(&entry.url))?;
+                writeln!(
+                    xml,
+                    "    <updated>{}</updated>",
+                    escape(date.as_deref().unwrap_or(&updated))
+                )?;
+                if let Some(date) = &date {
+                    writeln!(xml, "    <published>{}</published>", escape(date))?;
+                }
+                if !entry.item.description.is_empty() {
+                    writeln!(
+                        xml,
+                        "    <summary>{}</summary>",
+                        escape(&entry.item.description)
+                    )?;
+                }
+                if let Some(content) = &entry.content {
+                    writeln!(
+                        xml,
+                        r#"    <content type="html">{}</content>"#,
+                        escape(content)
+                    )?;
+                }
+                writeln!(xml, "  </entry>")?;
+            }
+            writeln!(xml, "</feed>")?;
+        }
+        FeedFormat::Rss => {
+            writeln!(
+                xml,
+                r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">"#
+            )?;
+            writeln!(xml, "  <channel>")?;
+            writeln!(xml, "    <title>{}</title>", escape(&title))?;
+            writeln!(xml, "    <link>{}</link>", escape(&home))?;
+            let description = config
+                .default_page_attributes
+                .description
+                .clone()
+                .unwrap_or_else(|| title.clone());
+            writeln!(
+                xml,
+                "    <description>{}</description>",
+                escape(&description)
+            )?;
+            writeln!(
+                xml,
+                r#"    <atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+                escape(&feed_url)
+            )?;
+            for entry in &entries {
+                writeln!(xml, "    <item>")?;
+                writeln!(xml, "      <title>{}</title>", escape(&entry.item.title))?;
+                writeln!(xml, "      <link>{}</link>", escape(&entry.url))?;
+                writeln!(
+                    xml,
+                    r#"      <guid isPermaLink="true">{}</guid>"#,
+                    escape(&entry.url)
+                )?;
+                if let Some(date) = entry.item.date.as_deref().and_then(rss_date) {
+                    writeln!(xml, "      <pubDate>{date}</pubDate>")?;
+                }
+                let description = entry.content.as_deref().unwrap_or(&entry.item.description);
+                writeln!(
+                    xml,
+                    "      <description>{}</description>",
+                    escape(description)
+                )?;
+                writeln!(xml, "    </item>")?;
+            }
+            writeln!(xml, "  </channel>")?;
+            writeln!(xml, "</rss>")?;
+        }
+    }
+
+    Ok(xml)
+}
+
+/// `<link rel="alternate">` elements for the feeds of the collection a page belongs to or
+/// lists.
+pub fn alternate_links(config: &Config, page_name: &str, page: &HtmlPage) -> Option<Markup> {
+    let collection = if page.pattern.is_some() {
+        page_name
+    } else {
+        page.listed_collection()?
+    };
+    let feeds: Vec<&Feed> = config
+        .feeds
+        .iter()
+        .filter(|feed| feed.collection == collection)
+        .collect();
+    if feeds.is_empty() {
+        return None;
+    }
+
+    Some(html! {
+        @for feed in feeds {
+            link rel="alternate"
+                type=(feed.format.media_type())
+                title=(title(config, feed))
+                href=(head::route_url(config, &feed.path));
+        }
+    })
+}
+
+/// The feed's own title, or the site's.
+fn title(config: &Config, feed: &Feed) -> String {
+    feed.title
+        .clone()
+        .or_else(|| config.default_page_attributes.title.clone())
+        .unwrap_or_default()
+}
+
+struct Entry<'a> {
+    item: &'a CollectionItem,
+    /// Absolute URL of the page
+    url: String,
+    content: Option<String>,
+}
+
+/// Prefixes the `href` and `src` attributes in `content` so they work outside the site.
+fn absolute_links(content: &str, site_url: &str, page_url: &str) -> String {
+    let (fragment, _) = dom::parse_fragment(content, "body");
+    let page_dir = &page_url[..page_url.rfind('/').map_or(0, |index| index + 1)];
+
+    for node in fragment.descendants() {
+        let Some(element) = node.as_element() else {
+            continue;
+        };
+        let mut attributes = element.attributes.borrow_mut();
+        for name in ["href", "src"] {
+            let Some(value) = attributes.get(name) else {
+                continue;
+            };
+            let absolute = if value.starts_with("//") || value.contains(':') {
+                continue;
+            } else if value.starts_with('#') {
+                format!("{site_url}{page_url}{value}")
+            } else if value.starts_with('/') {
+                format!("{site_url}{value}")
+            } else {
+                format!("{site_url}{page_dir}{value}")
+            };
+            attributes.insert(name, absolute);
+        }
+    }
+
+    dom::inner_html(&fragment)
+}
+
+/// Atom needs a full RFC 3339 timestamp: a date alone is taken as midnight UTC, a time
+/// without seconds gets `:00` and a time without an offset is UTC.
+fn atom_date(date: &str) -> String {
+    let (day, time) = date.split_at_checked(10).unwrap_or((date, ""));
+    let time = time.strip_prefix(['T', ' ']).unwrap_or(time);
+    let offset_start = time
+        .find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '.'))
+        .unwrap_or(time.len());
+    let (clock, offset) = time.split_at(offset_start);
+    let clock = match clock.matches(':').count() {
+        0 => "00:00:00".to_string(),
+        1 => format!("{clock}:00"),
+        _ => clock.to_string(),
+    };
+    let offset = if offset.is_empty() { "Z" } else { offset };
+    format!("{day}T{clock}{offset}")
+}
+
+/// RSS wants RFC 822 dates, e.g. `Fri, 02 Jan 2026 00:00:00 +0000`.
+fn rss_date(date: &str) -> Option<String> {
+    let number = |range: std::ops::Range<usize>| date.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = match date.get(10..11) {
+        Some("T" | " ") => (
+            number(11..13)?,
+            number(14..16)?,
+            number(17..19).unwrap_or(0),
+        ),
+        _ => (0, 0, 0),
+    };
+    let offset = match date
+        .get(19..)
+        .map(|rest| rest.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit()))
+    {
+        Some(offset) if offset.len() == 6 => offset.replace(':', ""),
+        _ => "+0000".to_string(),
+    };
+
+    const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    // 1970-01-01 was a Thursday
+    let weekday = (config::days_from_civil(year, month, day) + 4).rem_euclid(7) as usize;
+    Some(format!(
+        "{}, {day:02} {} {year:04} {hour:02}:{minute:02}:{second:02} {offset}",
+        WEEKDAYS[weekday],
+        MONTHS.get(usize::try_from(month - 1).ok()?)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atom_dates_are_full_rfc_3339_timestamps() {
+        assert_eq!(atom_date("2025-06-01"), "2025-06-01T00:00:00Z");
+        assert_eq!(atom_date("2025-06-01T09:00"), "2025-06-01T09:00:00Z");
+        assert_eq!(atom_date("2025-06-01 09:00:30"), "2025-06-01T09:00:30Z");
+        assert_eq!(atom_date("2025-06-01T09:00:30Z"), "2025-06-01T09:00:30Z");
+        assert_eq!(
+            atom_date("2025-06-01T09:00+02:00"),
+            "2025-06-01T09:00:00+02:00"
+        );
+        assert_eq!(
+            atom_date("2025-06-01T09:00:30.5-05:00"),
+            "2025-06-01T09:00:30.5-05:00"
+        );
+    }
+
+    #[test]
+    fn rss_dates_are_rfc_822() {
+        assert_eq!(
+            rss_date("2026-01-02").as_deref(),
+            Some("Fri, 02 Jan 2026 00:00:00 +0000")
+        );
+        assert_eq!(
+            rss_date("2025-06-01T09:30").as_deref(),
+            Some("Sun, 01 Jun 2025 09:30:00 +0000")
+        );
+        assert_eq!(
+            rss_date("2024-02-29T23:59:59+02:00").as_deref(),
+            Some("Thu, 29 Feb 2024 23:59:59 +0200")
+        );
+        assert_eq!(rss_date("June 1st"), None);
+    }
+
+    #[test]
+    fn rss_weekdays_follow_the_calendar() {
+        // Across a leap day, the turn of a century and dates before 1970
+        for (date, weekday) in [
+            ("1970-01-01", "Thu"),
+            ("1969-12-31", "Wed"),
+            ("2000-02-29", "Tue"),
+            ("2000-03-01", "Wed"),
+            ("2100-03-01", "Mon"),
+            ("1900-01-01", "Mon"),
+        ] {
+            assert!(
+                rss_date(date).unwrap().starts_with(weekday),
+                "{date} is a {weekday}"
+            );
+        }
+    }
+}
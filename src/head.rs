@@ -0,0 +1,328 @@
+use crate::config::{Config, Env, ResolvedAttributes};
+use crate::dom;
+use crate::prelude::*;
+use kuchikiki::NodeRef;
+use maud::{Markup, PreEscaped, html};
+use std::collections::BTreeMap;
+
+/// Attribute on a source `<head>` element that decides what happens when it conflicts with a
+/// generated one.
+const CONFLICT_ATTRIBUTE: &str = "data-bageri";
+
+/// What to do with a source head element that conflicts with a generated one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conflict {
+    /// The source element takes the place of the generated one
+    Replace,
+    /// The generated element is kept and the source element dropped
+    Skip,
+    /// Both elements are kept
+    Append,
+}
+
+/// Renders the generated `<head>` contents shared by SPA and HTML pages: charset, viewport,
+/// title, favicon, meta tags, scripts, styles, `window.ENV` and, in development, live reload.
+pub fn render_head(config: &Config, attributes: &ResolvedAttributes, env: Option<&Env>) -> Markup {
+    html! {
+        meta charset="UTF-8";
+        meta name="viewport" content="width=device-width, initial-scale=1.0";
+        title { (attributes.title) }
+        @if !attributes.favicon.is_empty() {
+            link rel="icon" href=(asset_url(config, &attributes.favicon));
+        }
+        (generate_meta_tags(attributes))
+        // Global scripts come first, then group and page scripts
+        @for script in &attributes.scripts {
+            script type="module" src=(asset_url(config, script)) {}
+        }
+        @for style in &attributes.styles {
+            link rel="stylesheet" href=(asset_url(config, style));
+        }
+        script {
+            (PreEscaped(format!("// Inject environment variables\nwindow.ENV = {};", generate_env_object(&config.env))))
+        }
+        @if matches!(env, Some(Env::Development)) {
+            script {
+                (PreEscaped(generate_live_reload_script()))
+            }
+        }
+    }
+}
+
+fn generate_meta_tags(attributes: &ResolvedAttributes) -> Markup {
+    html! {
+        @if !attributes.author.is_empty() {
+            meta name="author" content=(attributes.author);
+        }
+        @if !attributes.description.is_empty() {
+            meta name="description" content=(attributes.description);
+        }
+    }
+}
+
+/// Prefixes a relative asset URL with the configured `base_url`, e.g. `main.js` becomes
+/// `/apps/dashboard/main.js`.
+///
+/// Root-relative URLs (`/shared/theme.css`) are left untouched, so assets outside the site's
+/// sub-path can still be linked, as are absolute URLs (`https://...`, `//cdn...`, `data:`) and
+/// fragments.
+pub fn asset_url(config: &Config, url: &str) -> String {
+    prefix_base_url(&config.base_url, url)
+}
+
+/// [`asset_url`] for callers that only hold the base URL.
+pub fn prefix_base_url(base_url: &str, url: &str) -> String {
+    let is_absolute = url.starts_with('/')
+        || url.contains("://")
+        || url.starts_with("data:")
+        || url.starts_with('#');
+    if is_absolute || url.is_empty() {
+        return url.to_string();
+    }
+
+    format!("{base_url}{}", url.trim_start_matches("./"))
+}
+
+/// Public URL of a route such as `/blog/`, which is always served below `base_url`.
+pub fn route_url(config: &Config, route: &str) -> String {
+    format!("{}{}", config.base_url, route.trim_start_matches('/'))
+}
+
+fn generate_env_object(env: &BTreeMap<String, String>) -> String {
+    let entries = env
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                r#"            "{}": "{}""#,
+                escape_js(key),
+                escape_js(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    if entries.is_empty() {
+        "{}".to_string()
+    } else {
+        format!("{{\n{entries}\n        }}")
+    }
+}
+
+fn escape_js(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+fn generate_live_reload_script() -> String {
+    r#"
+// Live reload script for development
+(function() {
+    const source = new EventSource('/live-reload');
+    source.onmessage = function(event) {
+        if (event.data === 'reload') {
+            console.log('Files changed, reloading page...');
+            window.location.reload();
+        }
+    };
+    source.onerror = function(event) {
+        console.log('Live reload connection lost, will attempt to reconnect...');
+    };
+})();
+"#
+    .to_string()
+}
+
+/// Merges the `<head>` of a source page into the generated head.
+///
+/// Source elements are appended unless an identical element is already present. When a
+/// source element conflicts with a generated one (same title, meta name, favicon, script or
+/// stylesheet), the page wins for `<title>` and the description and the generated element wins
+/// otherwise. The `<head>` of a layout never wins by default, so configured values stay above
+/// the layout's. `data-bageri="replace|skip|append"` on the source element overrides that.
+pub fn merge_source_head(
+    config: &Config,
+    generated: Markup,
+    source_head: &NodeRef,
+    from_layout: bool,
+    input_file: &str,
+) -> Markup {
+    let (head, _) = dom::parse_fragment(&generated.into_string(), "head");
+
+    for node in source_head.children() {
+        if node.as_comment().is_some() {
+            head.append(node);
+            continue;
+        }
+        if node.as_element().is_none() {
+            continue;
+        }
+
+        let requested = dom::take_attribute(&node, CONFLICT_ATTRIBUTE);
+        let key = conflict_key(config, &node);
+        let existing = key.as_ref().and_then(|key| {
+            head.children()
+                .find(|generated| conflict_key(config, generated).as_ref() == Some(key))
+        });
+
+        match existing {
+            Some(existing) => {
+                let conflict = match requested.as_deref() {
+                    Some("replace") => Conflict::Replace,
+                    Some("skip") => Conflict::Skip,
+                    Some("append") => Conflict::Append,
+                    Some(other) => {
+                        warn!(
+                            "{}: unknown {}=\"{}\", expected replace, skip or append",
+                            input_file, CONFLICT_ATTRIBUTE, other
+                        );
+                        default_conflict(key.as_deref(), from_layout)
+                    }
+                    None => default_conflict(key.as_deref(), from_layout),
+                };
+
+                match conflict {
+                    Conflict::Replace => {
+                        existing.insert_before(node);
+                        existing.detach();
+                    }
+                    Conflict::Skip => {
+                        debug!("{}: dropping duplicate head element {}", input_file, node);
+                    }
+                    Conflict::Append => head.append(node),
+                }
+            }
+            None => {
+                let markup = node.to_string();
+                if head
+                    .children()
+                    .any(|generated| generated.to_string() == markup)
+                {
+                    debug!("{}: dropping duplicate head element {}", input_file, markup);
+                } else {
+                    head.append(node);
+                }
+            }
+        }
+    }
+
+    PreEscaped(dom::inner_html(&head))
+}
+
+/// The page's own title and description override the configured ones by default.
+fn default_conflict(key: Option<&str>, from_layout: bool) -> Conflict {
+    match key {
+        Some("title") | Some("meta:name:description") if !from_layout => Conflict::Replace,
+        _ => Conflict::Skip,
+    }
+}
+
+/// Identifies head elements of which only one should survive, so that source and generated
+/// elements can be matched. Asset URLs are compared after `base_url` is applied.
+fn conflict_key(config: &Config, node: &NodeRef) -> Option<String> {
+    let attribute = |name| dom::attribute(node, name);
+
+    match dom::element_name(node)? {
+        "title" => Some("title".to_string()),
+        "base" => Some("base".to_string()),
+        "meta" => {
+            if attribute("charset").is_some() {
+                Some("meta:charset".to_string())
+            } else if let Some(name) = attribute("name") {
+                Some(format!("meta:name:{}", name.to_lowercase()))
+            } else if let Some(property) = attribute("property") {
+                Some(format!("meta:property:{property}"))
+            } else {
+                attribute("http-equiv")
+                    .map(|equiv| format!("meta:http-equiv:{}", equiv.to_lowercase()))
+            }
+        }
+        "link" => {
+            let rel = attribute("rel")?.to_lowercase();
+            match rel.as_str() {
+                "icon" | "shortcut icon" => Some("link:icon".to_string()),
+                "canonical" | "manifest" => Some(format!("link:{rel}")),
+                "stylesheet" => attribute("href")
+                    .map(|href| format!("link:stylesheet:{}", asset_url(config, &href))),
+                _ => None,
+            }
+        }
+        "script" => attribute("src").map(|src| format!("script:{}", asset_url(config, &src))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PageAttributes, SpaPage};
+    use crate::html;
+
+    fn config() -> Config {
+        Config {
+            default_page_attributes: PageAttributes {
+                favicon: Some("icon.png".to_string()),
+                author: Some("Ada".to_string()),
+                description: Some("A site".to_string()),
+                scripts: Some(vec!["main.js".to_string()]),
+                styles: Some(vec!["main.css".to_string()]),
+                ..PageAttributes::builtin()
+            },
+            env: BTreeMap::from([("API_URL".to_string(), "https://api.example.com".to_string())]),
+            ..Config::default()
+        }
+    }
+
+    /// The `<head>` of a rendered document, as the parser sees it.
+    fn head_of(document: &str) -> String {
+        let (document, _) = dom::parse_document(document);
+        dom::inner_html(&dom::select_first(&document, "head").unwrap())
+    }
+
+    /// Renders the same page as an SPA page and as an HTML page without a head of its own.
+    fn heads(env: Env) -> (String, String) {
+        let config = config();
+        let page = SpaPage::default();
+        let spa = html::generate_html(
+            &config,
+            "index",
+            &page,
+            "/",
+            Some(&env),
+            &mut std::collections::BTreeSet::new(),
+        )
+        .unwrap();
+
+        let attributes = config.resolve_attributes(None, &page.attributes);
+        let source = html::parse_source_page("<p>Hello</p>", "index.html").unwrap();
+        let html_page =
+            html::render_source_page(&config, &attributes, source, Some(&env), "index.html");
+
+        (head_of(&spa), head_of(&html_page))
+    }
+
+    #[test]
+    fn spa_and_html_pages_share_the_generated_head() {
+        for env in [Env::Production, Env::Development] {
+            let (spa, html_page) = heads(env);
+            assert_eq!(spa, html_page);
+
+            for expected in [
+                r#"<link rel="icon" href="/icon.png">"#,
+                r#"<meta name="author" content="Ada">"#,
+                r#"<meta name="description" content="A site">"#,
+                r#"<script type="module" src="/main.js"></script>"#,
+                r#"<link rel="stylesheet" href="/main.css">"#,
+                r#""API_URL": "https://api.example.com""#,
+            ] {
+                assert!(spa.contains(expected), "missing {expected} in {spa}");
+            }
+            assert_eq!(
+                spa.contains("/live-reload"),
+                matches!(env, Env::Development)
+            );
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::config::{Config, HighlightMode};
+use crate::dom;
+use crate::head;
+use crate::html::SourcePage;
+use crate::prelude::*;
+use color_eyre::eyre::{Result, eyre};
+use kuchikiki::NodeRef;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::LazyLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+use syntect::html::{
+    ClassStyle, ClassedHTMLGenerator, IncludeBackground, append_highlighted_html_for_styled_line,
+    css_for_theme_with_class_style,
+};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Loading the bundled syntaxes and themes takes a while, so it happens once per process.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Prefix of the classes syntect emits, so they do not clash with the site's own.
+const CLASS_PREFIX: &str = "hl-";
+
+/// `class="language-rust"` on a `<code>` element selects the syntax.
+const LANGUAGE_PREFIX: &str = "language-";
+
+/// Highlighting used when the theme does not define a line highlight color.
+const FALLBACK_LINE_HIGHLIGHT: Color = Color {
+    r: 255,
+    g: 255,
+    b: 0,
+    a: 48,
+};
+
+/// Highlights the code blocks of a page and links the theme's stylesheet if any were.
+/// Returns true if the page links the stylesheet, which is only written for such pages.
+///
+/// Every `<pre><code class="language-x">` is highlighted, whether it was written by hand or
+/// converted from a Markdown fence. Blocks in languages syntect does not know are left alone.
+///
+/// Lines are numbered with `data-line-numbers` on the `<pre>` or `<code>`, and highlighted with
+/// `data-highlight="2,4-6"`. Markdown fences take the same options after the language:
+/// ```` ```rust numbers {2,4-6} ````.
+pub fn highlight_page(config: &Config, page: &SourcePage, input_file: &str) -> Result<bool> {
+    if !config.highlight.enabled {
+        return Ok(false);
+    }
+
+    let blocks: Vec<NodeRef> = page
+        .body
+        .descendants()
+        .filter(|node| dom::element_name(node) == Some("code"))
+        .filter(|node| {
+            node.parent()
+                .is_some_and(|parent| dom::element_name(&parent) == Some("pre"))
+        })
+        .collect();
+
+    let mut theme = None;
+    let mut highlighted = false;
+    for code in blocks {
+        let Some(language) = language(&code) else {
+            continue;
+        };
+        let Some(syntax) = SYNTAXES.find_syntax_by_token(&language) else {
+            debug!(
+                "{}: no syntax for language '{}', leaving the code block as is",
+                input_file, language
+            );
+            continue;
+        };
+        let theme = match &theme {
+            Some(theme) => theme,
+            None => theme.insert(load_theme(config)?),
+        };
+
+        highlight_block(config, &code, syntax, theme, input_file)
+            .wrap_err_with(|| format!("Failed to highlight {language} code in {input_file}"))?;
+        highlighted = true;
+    }
+
+    let links_stylesheet = highlighted && config.highlight.mode == HighlightMode::Classes;
+    if links_stylesheet {
+        let markup = format!(
+            r#"<link rel="stylesheet" href="{}">"#,
+            head::route_url(config, &config.highlight.stylesheet)
+        );
+        dom::append_markup(&page.head, &markup);
+    }
+
+    Ok(links_stylesheet)
+}
+
+/// Returns the stylesheet for the configured theme, with the rules for numbered and
+/// highlighted lines.
+pub fn stylesheet(config: &Config) -> Result<String> {
+    let theme = load_theme(config)?;
+    let mut css = css_for_theme_with_class_style(
+        &theme,
+        ClassStyle::SpacedPrefixed {
+            prefix: CLASS_PREFIX,
+        },
+    )?;
+
+    let code = format!(".{CLASS_PREFIX}code");
+    let highlight = css_color(
+        theme
+            .settings
+            .line_highlight
+            .unwrap_or(FALLBACK_LINE_HIGHLIGHT),
+    );
+    let gutter = theme
+        .settings
+        .gutter_foreground
+        .map(|color| format!(" color: {};\n", css_color(color)))
+        .unwrap_or_default();
+    write!(
+        css,
+        "\n{code} .line {{\n display: block;\n}}\n\
+         {code} .line.highlighted {{\n background-color: {highlight};\n}}\n\
+         {code} .line-number {{\n display: inline-block;\n min-width: 2ch;\n margin-right: 1em;\n \
+         text-align: right;\n opacity: 0.6;\n user-select: none;\n{gutter}}}\n"
+    )?;
+    Ok(css)
+}
+
+/// Returns one of syntect's bundled themes by name, or loads a `.tmTheme` file.
+fn load_theme(config: &Config) -> Result<Theme> {
+    let name = &config.highlight.theme;
+    if let Some(theme) = THEMES.themes.get(name) {
+        return Ok(theme.clone());
+    }
+    if name.ends_with(".tmTheme") {
+        return ThemeSet::get_theme(Path::new(name))
+            .wrap_err_with(|| format!("Failed to load highlight theme: {name}"));
+    }
+
+    let known: Vec<&str> = THEMES.themes.keys().map(String::as_str).collect();
+    Err(eyre!(
+        "Unknown highlight theme '{name}', expected a .tmTheme file or one of: {}",
+        known.join(", ")
+    ))
+}
+
+/// Returns `rust` for `<code class="language-rust">`.
+fn language(code: &NodeRef) -> Option<String> {
+    dom::attribute(code, "class")?
+        .split_whitespace()
+        .find_map(|class| class.strip_prefix(LANGUAGE_PREFIX))
+        .filter(|language| !language.is_empty())
+        .map(str::to_string)
+}
+
+/// Which lines of a block are numbered and highlighted.
+struct LineOptions {
+    numbers: bool,
+    highlighted: BTreeSet<usize>,
+}
+
+fn line_options(config: &Config, code: &NodeRef, input_file: &str) -> LineOptions {
+    let pre = code.parent();
+    let elements = || pre.iter().chain(std::iter::once(code));
+
+    let mut options = LineOptions {
+        numbers: config.highlight.line_numbers
+            || elements().any(|element| dom::attribute(element, "data-line-numbers").is_some()),
+        highlighted: BTreeSet::new(),
+    };
+    let mut ranges: Vec<String> = elements()
+        .filter_map(|element| dom::attribute(element, "data-highlight"))
+        .collect();
+
+    // The rest of a Markdown fence's info string, e.g. `numbers {2,4-6}`
+    if let Some(meta) = dom::attribute(code, "data-meta") {
+        for token in meta.split_whitespace() {
+            if matches!(token, "numbers" | "linenos" | "line-numbers") {
+                options.numbers = true;
+            } else if let Some(lines) = token
+                .strip_prefix('{')
+                .and_then(|token| token.strip_suffix('}'))
+                .or_else(|| token.strip_prefix("highlight="))
+            {
+                ranges.push(lines.to_string());
+            }
+        }
+    }
+
+    for range in ranges.iter().flat_map(|ranges| ranges.split(',')) {
+        let range = range.trim();
+        if range.is_empty() {
+            continue;
+        }
+        let parsed: Option<(usize, usize)> = match range.split_once('-') {
+            Some((start, end)) => start.trim().parse().ok().zip(end.trim().parse().ok()),
+            None => range.parse().ok().map(|line| (line, line)),
+        };
+        match parsed {
+            Some((start, end)) => options.highlighted.extend(start..=end),
+            None => warn!(
+                "{}: invalid line range '{}' to highlight",
+                input_file, range
+            ),
+        }
+    }
+
+    options
+}
+
+/// Replaces the text of a `<code>` element with highlighted lines.
+fn highlight_block(
+    config: &Config,
+    code: &NodeRef,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    input_file: &str,
+) -> Result<()> {
+    let options = line_options(config, code, input_file);
+    let mut text = code.text_contents();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+
+    let html = match config.highlight.mode {
+        HighlightMode::Classes => {
+            let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                syntax,
+                &SYNTAXES,
+                ClassStyle::SpacedPrefixed {
+                    prefix: CLASS_PREFIX,
+                },
+            );
+            for line in LinesWithEndings::from(&text) {
+                generator.parse_html_for_line_which_includes_newline(line)?;
+            }
+            generator.finalize()
+        }
+        HighlightMode::Inline => {
+            let mut highlighter = HighlightLines::new(syntax, theme);
+            let mut html = String::new();
+            for line in LinesWithEndings::from(&text) {
+                let regions = highlighter.highlight_line(line, &SYNTAXES)?;
+                append_highlighted_html_for_styled_line(
+                    &regions,
+                    IncludeBackground::No,
+                    &mut html,
+                )?;
+            }
+            html
+        }
+    };
+
+    let inline = config.highlight.mode == HighlightMode::Inline;
+    let line_highlight = css_color(
+        theme
+            .settings
+            .line_highlight
+            .unwrap_or(FALLBACK_LINE_HIGHLIGHT),
+    );
+    let mut lines = String::new();
+    for (index, line) in split_lines(&html).iter().enumerate() {
+        let number = index + 1;
+        let highlighted = options.highlighted.contains(&number);
+        lines.push_str(if highlighted {
+            r#"<span class="line highlighted""#
+        } else {
+            r#"<span class="line""#
+        });
+        if inline && !options.highlighted.is_empty() {
+            lines.push_str(r#" style="display:block"#);
+            if highlighted {
+                write!(lines, ";background-color:{line_highlight}")?;
+            }
+            lines.push('"');
+        }
+        lines.push('>');
+        if options.numbers {
+            lines.push_str(r#"<span class="line-number" aria-hidden="true""#);
+            if inline {
+                lines.push_str(
+                    r#" style="display:inline-block;min-width:2ch;margin-right:1em;text-align:right;opacity:0.6;user-select:none""#,
+                );
+            }
+            write!(lines, ">{number}</span>")?;
+        }
+        lines.push_str(line);
+        lines.push_str("\n</span>");
+    }
+
+    for child in code.children().collect::<Vec<_>>() {
+        child.detach();
+    }
+    dom::append_markup(code, &lines);
+
+    if let Some(pre) = code.parent()
+        && let Some(pre) = pre.as_element()
+    {
+        let mut attributes = pre.attributes.borrow_mut();
+        if inline {
+            let mut style = String::new();
+            if let Some(background) = theme.settings.background {
+                write!(style, "background-color:{};", css_color(background))?;
+            }
+            if let Some(foreground) = theme.settings.foreground {
+                write!(style, "color:{};", css_color(foreground))?;
+            }
+            if let Some(existing) = attributes.get("style") {
+                style.push_str(existing);
+            }
+            attributes.insert("style", style);
+        } else {
+            let class = dom::union_classes(
+                attributes.get("class").unwrap_or_default(),
+                &format!("{CLASS_PREFIX}code"),
+            );
+            attributes.insert("class", class);
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits highlighted HTML into lines, closing the spans still open at the end of a line and
+/// reopening them on the next, so every line is well-formed on its own.
+fn split_lines(html: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut has_text = false;
+
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("<span")
+            && let Some(end) = rest.find('>')
+        {
+            open.push(&rest[..=end]);
+            line.push_str(&rest[..=end]);
+            rest = &rest[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("</span>") {
+            open.pop();
+            line.push_str("</span>");
+            rest = after;
+        } else if c == '\n' {
+            line.push_str(&"</span>".repeat(open.len()));
+            lines.push(std::mem::take(&mut line));
+            line.extend(open.iter().copied());
+            has_text = false;
+            rest = &rest[1..];
+        } else {
+            has_text = true;
+            line.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    // After the last newline there is usually nothing but the spans closing
+    if has_text {
+        line.push_str(&"</span>".repeat(open.len()));
+        lines.push(line);
+    }
+    lines
+}
+
+fn css_color(color: Color) -> String {
+    if color.a == 255 {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+    } else {
+        format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            color.r, color.g, color.b, color.a
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_plain_lines() {
+        assert_eq!(split_lines("a\n\nb\n"), ["a", "", "b"]);
+        assert_eq!(split_lines("a\nb"), ["a", "b"]);
+        assert_eq!(split_lines("é → ü\n"), ["é → ü"]);
+    }
+
+    #[test]
+    fn reopens_nested_spans_on_the_next_line() {
+        let html = r#"<span class="a"><span class="b">one
+two</span></span>
+"#;
+        assert_eq!(
+            split_lines(html),
+            [
+                r#"<span class="a"><span class="b">one</span></span>"#,
+                r#"<span class="a"><span class="b">two</span></span>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn only_reopens_the_spans_still_open() {
+        let html = r#"<span class="a">x<span class="b">y</span>
+z</span>
+"#;
+        assert_eq!(
+            split_lines(html),
+            [
+                r#"<span class="a">x<span class="b">y</span></span>"#,
+                r#"<span class="a">z</span>"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_the_spans_closing_after_the_last_newline() {
+        let html = "<span class=\"a\">x\n</span>";
+        assert_eq!(split_lines(html), [r#"<span class="a">x</span>"#]);
+    }
+}
@@ -1,120 +1,138 @@
-use crate::config::{Config, Env, HtmlPage, PageAttributes, SpaPage};
+use crate::component::Components;
+use crate::config::{Config, Env, HtmlPage, Pagination, ResolvedAttributes, SpaPage};
 use crate::prelude::*;
-use color_eyre::eyre::{Context, Result};
+use crate::sitemap::{self, SitemapUrl};
+use crate::template::{self, PageMeta, Templates};
+use crate::{config, dom, feed, head, highlight, include, layout, markdown, metadata, route, toc};
+use color_eyre::eyre::{Context, Result, eyre};
+use indexmap::IndexMap;
+use kuchikiki::NodeRef;
 use maud::{DOCTYPE, Markup, PreEscaped, html};
-use std::collections::HashMap;
+use minijinja::value::{Value, ValueKind};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
 
-pub fn generate_html(config: &Config, page: &SpaPage, env: Option<&Env>) -> String {
-    let title = if page.attributes.title.is_empty() {
-        &config.default_page_attributes.title
-    } else {
-        &page.attributes.title
-    };
+/// Renders an SPA page, adding the layouts, partials, components and data it read to
+/// `dependencies`.
+pub fn generate_html(
+    config: &Config,
+    page_name: &str,
+    page: &SpaPage,
+    url: &str,
+    env: Option<&Env>,
+    dependencies: &mut BTreeSet<PathBuf>,
+) -> Result<String> {
+    let attributes = config.resolve_attributes(page.group.as_deref(), &page.attributes);
+    let noindex = page.sitemap.noindex.unwrap_or(false);
+    let (templates, attributes) = page_templates(
+        config,
+        &attributes,
+        env,
+        PageMeta {
+            name: page_name,
+            source: "",
+            group: page.group.as_deref(),
+            url: url.to_string(),
+            meta: Value::from_serialize(BTreeMap::<String, String>::new()),
+            previous: None,
+            next: None,
+        },
+        None,
+        None,
+    )?;
+
+    if let Some(layout) = &page.layout {
+        // The app container becomes the page content placed in the layout's main slot
+        let layout = Path::new("src").join(layout);
+        let label = layout.display().to_string();
+        let source = parse_source_page(r#"<div id="app"></div>"#, &label)?;
+        let mut components = Components::new(&label);
+        let source = layout::apply_layout(
+            source,
+            &layout,
+            &label,
+            &templates,
+            &mut components,
+            dependencies,
+        )?;
+        components.insert_styles(&source.head);
+        if noindex {
+            dom::append_markup(&source.head, &sitemap::noindex_meta().into_string());
+        }
+        dependencies.extend(templates.used_data());
+        return Ok(render_source_page(config, &attributes, source, env, &label));
+    }
 
     let markup = html! {
-        (DOCTYPE)
-        html lang="en" {
-            head {
-                meta charset="UTF-8";
-                meta name="viewport" content="width=device-width, initial-scale=1.0";
-                title { (title) }
-                @if !page.attributes.favicon.is_empty() || !config.default_page_attributes.favicon.is_empty() {
-                    @let favicon = if !page.attributes.favicon.is_empty() { &page.attributes.favicon } else { &config.default_page_attributes.favicon };
-                    link rel="icon" href=(favicon);
-                }
-                (generate_meta_tags(&page.attributes, &config.default_page_attributes))
-                // Include global scripts first
-                @for script in &config.default_page_attributes.scripts {
-                    script type="module" src=(script) {}
-                }
-
-                // Then include page-specific scripts
-                @for script in &page.attributes.scripts {
-                    script type="module" src=(script) {}
-                }
-
-                @for style in &config.default_page_attributes.styles {
-                    link rel="stylesheet" href=(style);
-                }
-
-                @for style in &page.attributes.styles {
-                    link rel="stylesheet" href=(style);
-                }
-                script {
-                    (PreEscaped(format!("// Inject environment variables\nwindow.ENV = {};", generate_env_object(&config.env))))
-                }
-                @if matches!(env, Some(Env::Development)) {
-                    script {
-                        (PreEscaped(generate_live_reload_script()))
-                    }
-                }
-            }
-            body {
-                div id="app" {}
+        head {
+            (head::render_head(config, &attributes, env))
+            @if noindex {
+                (sitemap::noindex_meta())
             }
         }
+        body {
+            div id="app" {}
+        }
     };
+    dependencies.extend(templates.used_data());
 
-    markup.into_string()
+    Ok(render_document(
+        &html_element_attributes(&attributes, None),
+        markup,
+    ))
 }
 
-fn generate_meta_tags(page_attrs: &PageAttributes, default_attrs: &PageAttributes) -> Markup {
-    html! {
-        @if !page_attrs.author.is_empty() || !default_attrs.author.is_empty() {
-            @let author = if !page_attrs.author.is_empty() { &page_attrs.author } else { &default_attrs.author };
-            meta name="author" content=(author);
-        }
-        @if !page_attrs.description.is_empty() || !default_attrs.description.is_empty() {
-            @let description = if !page_attrs.description.is_empty() { &page_attrs.description } else { &default_attrs.description };
-            meta name="description" content=(description);
+/// Collects the `<html>` element attributes: `lang`, `dir` and `class` first, then any other
+/// configured ones. Attributes on the source document's `<html>` win, except `class`, which is
+/// combined with the configured classes.
+fn html_element_attributes(
+    attributes: &ResolvedAttributes,
+    source_html: Option<&NodeRef>,
+) -> Vec<(String, String)> {
+    let mut merged: IndexMap<String, String> = IndexMap::new();
+    for (name, value) in [
+        ("lang", &attributes.lang),
+        ("dir", &attributes.dir),
+        ("class", &attributes.class),
+    ] {
+        if !value.is_empty() {
+            merged.insert(name.to_string(), value.clone());
         }
     }
-}
-
-fn generate_env_object(env: &HashMap<String, String>) -> String {
-    let entries = env
-        .iter()
-        .map(|(key, value)| {
-            format!(
-                r#"            "{}": "{}""#,
-                escape_js(key),
-                escape_js(value)
-            )
-        })
-        .collect::<Vec<_>>()
-        .join(",\n");
+    merged.extend(attributes.html_attributes.clone());
 
-    if entries.is_empty() {
-        "{}".to_string()
-    } else {
-        format!("{{\n{entries}\n        }}")
+    if let Some(element) = source_html.and_then(|node| node.as_element()) {
+        for (name, attribute) in element.attributes.borrow().map.iter() {
+            let name = name.local.to_string();
+            let value = match merged.get(&name) {
+                Some(existing) if name == "class" => dom::union_classes(existing, &attribute.value),
+                _ => attribute.value.clone(),
+            };
+            merged.insert(name, value);
+        }
     }
-}
 
-fn escape_js(s: &str) -> String {
-    s.replace('\\', "\\\\")
-        .replace('"', "\\\"")
-        .replace('\n', "\\n")
-        .replace('\r', "\\r")
-        .replace('\t', "\\t")
+    merged.into_iter().collect()
 }
 
-fn generate_live_reload_script() -> String {
-    r#"
-// Live reload script for development
-(function() {
-    const source = new EventSource('/live-reload');
-    source.onmessage = function(event) {
-        if (event.data === 'reload') {
-            console.log('Files changed, reloading page...');
-            window.location.reload();
-        }
-    };
-    source.onerror = function(event) {
-        console.log('Live reload connection lost, will attempt to reconnect...');
-    };
-})();
-"#.to_string()
+/// Wraps rendered head and body markup in the doctype and `<html>` element.
+fn render_document(html_attributes: &[(String, String)], content: Markup) -> String {
+    let mut document = DOCTYPE.into_string();
+    document.push_str("<html");
+    for (name, value) in html_attributes {
+        document.push(' ');
+        document.push_str(name);
+        document.push_str("=\"");
+        maud::Escaper::new(&mut document)
+            .write_str(value)
+            .expect("writing to a String cannot fail");
+        document.push('"');
+    }
+    document.push('>');
+    document.push_str(&content.into_string());
+    document.push_str("</html>");
+    document
 }
 
 pub async fn find_html_files(page_name: &str, page: &HtmlPage) -> Result<Vec<String>> {
@@ -131,15 +149,18 @@ pub async fn find_html_files(page_name: &str, page: &HtmlPage) -> Result<Vec<Str
             .wrap_err("Failed to read directory entry")?
         {
             let path = entry.path();
-            if let Some(file_name) = path.file_name() {
-                if let Some(name_str) = file_name.to_str() {
-                    if name_str.ends_with(".html") && glob_match(pattern, name_str) {
-                        files.push(path.to_string_lossy().to_string());
-                    }
-                }
+            if let Some(file_name) = path.file_name()
+                && let Some(name_str) = file_name.to_str()
+                && (name_str.ends_with(".html") || markdown::is_markdown(name_str))
+                && glob_match(pattern, name_str)
+            {
+                files.push(path.to_string_lossy().to_string());
             }
         }
 
+        // Directory order is filesystem dependent, keep builds reproducible
+        files.sort();
+
         if files.is_empty() {
             warn!("No HTML files found matching pattern '{}' in src/", pattern);
         }
@@ -147,11 +168,7 @@ pub async fn find_html_files(page_name: &str, page: &HtmlPage) -> Result<Vec<Str
         Ok(files)
     } else {
         // Use page name as filename
-        let filename = if page_name == "index" {
-            "src/index.html".to_string()
-        } else {
-            format!("src/{}.html", page_name)
-        };
+        let filename = format!("src/{}", page.get_source_files(page_name));
 
         if tokio::fs::metadata(&filename).await.is_ok() {
             Ok(vec![filename])
@@ -177,94 +194,471 @@ fn glob_match(pattern: &str, filename: &str) -> bool {
     }
 }
 
-pub async fn process_html_page(
+/// A processed HTML page and every file that was read to produce it.
+pub struct ProcessedPage {
+    pub html: String,
+    pub dependencies: BTreeSet<PathBuf>,
+    /// The rendered source without its layout, for the feeds of pattern pages
+    pub content: Option<String>,
+    /// `None` for a `noindex` or draft page
+    pub sitemap: Option<SitemapUrl>,
+    /// Whether the page links the highlight stylesheet
+    pub highlighted: bool,
+}
+
+/// One file written for a source file of an HTML page.
+pub struct PageOutput {
+    /// URL path of the file, without `base_url`
+    pub route: String,
+    pub record: Option<Value>,
+    pub pagination: Option<Value>,
+}
+
+/// Returns the files a source file of an HTML page is written to: one per record of a
+/// `from_data` page, one per tag of a `tags_of` page, one per page of a paginated page, and a
+/// single one otherwise.
+pub fn page_outputs(
     config: &Config,
+    page_name: &str,
     page: &HtmlPage,
     input_file: &str,
+) -> Result<Vec<PageOutput>> {
+    let pretty = |route: String| {
+        if config.pretty_urls {
+            route::prettify(&route)
+        } else {
+            route
+        }
+    };
+
+    if let Some(paginate) = &page.paginate {
+        return pagination_outputs(config, page_name, page, paginate, pretty);
+    }
+
+    let records = match (&page.from_data, &page.tags_of) {
+        (Some(path), _) => data_records(config, page, path)?,
+        (None, Some(collection)) => tag_records(config, page, collection)?,
+        (None, None) => {
+            let page_route = if page.pattern.is_some() {
+                // For pattern-based files, the route is derived from the source filename
+                route::pattern_route(page.route.as_deref(), input_file)
+            } else {
+                route::page_route(page_name, page.route.as_deref())
+            }?;
+            return Ok(vec![PageOutput {
+                route: pretty(page_route),
+                record: None,
+                pagination: None,
+            }]);
+        }
+    };
+
+    records
+        .into_iter()
+        .map(|record| {
+            let page_route =
+                route::record_route(page.route.as_deref(), |field| record_field(&record, field))?;
+            Ok(PageOutput {
+                route: pretty(page_route),
+                record: Some(record),
+                pagination: None,
+            })
+        })
+        .collect()
+}
+
+/// One output per page of a paginated collection listing.
+///
+/// Templates see the page as `pagination`: its `number`, `items`, `total_pages`,
+/// `total_items` and `per_page`, the `previous`, `next`, `first` and `last` URLs, and `pages`
+/// with the `number` and `url` of every page.
+fn pagination_outputs(
+    config: &Config,
+    page_name: &str,
+    page: &HtmlPage,
+    paginate: &Pagination,
+    pretty: impl Fn(String) -> String,
+) -> Result<Vec<PageOutput>> {
+    if page.pattern.is_some() || page.from_data.is_some() || page.tags_of.is_some() {
+        return Err(eyre!(
+            "A paginated page cannot also have a pattern, from_data or tags_of"
+        ));
+    }
+    if paginate.per_page == 0 {
+        return Err(eyre!("per_page of a paginated page must be at least 1"));
+    }
+
+    let items = config.collections.items(&paginate.collection)?;
+    let total_pages = items.len().div_ceil(paginate.per_page).max(1);
+    let first_route = route::page_route(page_name, page.route.as_deref())?;
+    let routes = (1..=total_pages)
+        .map(|number| Ok(pretty(route::numbered_route(&first_route, number)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let urls: Vec<String> = routes
+        .iter()
+        .map(|page_route| head::route_url(config, page_route))
+        .collect();
+    let pages: Vec<Value> = urls
+        .iter()
+        .enumerate()
+        .map(|(index, url)| minijinja::context! { number => index + 1, url => url })
+        .collect();
+
+    Ok(routes
+        .into_iter()
+        .enumerate()
+        .map(|(index, page_route)| {
+            let start = index * paginate.per_page;
+            let end = (start + paginate.per_page).min(items.len());
+            let pagination = minijinja::context! {
+                number => index + 1,
+                items => Value::from_serialize(&items[start..end]),
+                total_pages => total_pages,
+                total_items => items.len(),
+                per_page => paginate.per_page,
+                previous => index.checked_sub(1).map(|previous| urls[previous].clone()),
+                next => urls.get(index + 1).cloned(),
+                first => urls[0].clone(),
+                last => urls[total_pages - 1].clone(),
+                pages => pages.clone(),
+            };
+            PageOutput {
+                route: page_route,
+                record: None,
+                pagination: Some(pagination),
+            }
+        })
+        .collect())
+}
+
+/// One output file of an HTML page.
+pub struct PageInput<'a> {
+    pub page_name: &'a str,
+    pub page: &'a HtmlPage,
+    pub input_file: &'a str,
+    /// Public URL of the output, including `base_url`
+    pub url: String,
+    /// The record a `from_data` page is rendered for
+    pub record: Option<&'a Value>,
+    /// The page of a paginated collection listing
+    pub pagination: Option<&'a Value>,
+}
+
+/// Renders one source file of an HTML page, or returns `None` for a draft or scheduled page
+/// the build does not publish.
+///
+/// The source and its layouts are evaluated as templates first, then parsed, so template
+/// output goes through the same include, layout and head handling as hand-written markup.
+pub async fn process_html_page(
+    config: &Config,
+    input: &PageInput<'_>,
     env: Option<&Env>,
-) -> Result<String> {
+) -> Result<Option<ProcessedPage>> {
+    let PageInput {
+        page_name,
+        page,
+        input_file,
+        ..
+    } = *input;
     let content = tokio::fs::read_to_string(input_file)
         .await
         .wrap_err_with(|| format!("Failed to read HTML file: {}", input_file))?;
+    let mut dependencies = BTreeSet::from([PathBuf::from(input_file)]);
+
+    // The file's own metadata adds to the configured attributes; Markdown is converted as
+    // written
+    let is_markdown = markdown::is_markdown(input_file);
+    let (metadata, content) = metadata::read(&content, input_file, is_markdown, &mut dependencies)?;
+    let content = if is_markdown {
+        markdown::to_html(&content)
+    } else {
+        content
+    };
+    let published = metadata::is_published(config, page, &metadata, input.record)
+        .wrap_err_with(|| format!("Invalid publishing settings in {input_file}"))?;
+    if !published {
+        return Ok(None);
+    }
+    let layout = metadata
+        .front_matter
+        .layout
+        .as_ref()
+        .or(page.layout.as_ref());
+    let sitemap_settings = page.sitemap.overridden_by(&metadata.front_matter.sitemap);
+    let noindex = sitemap_settings.noindex.unwrap_or(false);
+    // Drafts are only built in development or with --include-drafts, and are not listed
+    let draft = metadata::is_draft(page, &metadata, input.record);
+    // The modification time differs between checkouts, so a reproducible build uses
+    // SOURCE_DATE_EPOCH instead
+    let lastmod = match metadata::date_field(&metadata.meta, "lastmod") {
+        Some(lastmod) => Some(lastmod),
+        None => match config::source_date_epoch() {
+            Some(timestamp) => Some(config::utc_timestamp(timestamp)),
+            None => tokio::fs::metadata(input_file)
+                .await
+                .and_then(|file| file.modified())
+                .ok()
+                .map(config::utc_timestamp),
+        },
+    };
 
-    let body_content = extract_body_content(&content)?;
+    let mut attributes = config.resolve_source_attributes(
+        page.group.as_deref(),
+        &page.attributes,
+        &metadata.front_matter.attributes,
+    );
+    if let Some(record) = input.record {
+        apply_record_attributes(&mut attributes, record);
+    }
+    let (previous, next) = config.collections.neighbours(page_name, input_file);
+    for item in previous.iter().chain(next.iter()) {
+        dependencies.extend(item.files().iter().cloned());
+    }
 
-    let title = if page.attributes.title.is_empty() {
-        &config.default_page_attributes.title
+    let (mut templates, mut attributes) = page_templates(
+        config,
+        &attributes,
+        env,
+        PageMeta {
+            name: page_name,
+            source: input_file,
+            group: page.group.as_deref(),
+            url: input.url.clone(),
+            meta: metadata.meta,
+            previous,
+            next,
+        },
+        input.record,
+        input.pagination,
+    )?;
+    templates.set_templating(page.is_templated());
+    let content = if page.is_templated() && !is_markdown {
+        templates.render(input_file, &content)?
     } else {
-        &page.attributes.title
+        content
     };
 
-    let markup = html! {
-        (DOCTYPE)
-        html lang="en" {
-            head {
-                meta charset="UTF-8";
-                meta name="viewport" content="width=device-width, initial-scale=1.0";
-                title { (title) }
-                @if !page.attributes.favicon.is_empty() || !config.default_page_attributes.favicon.is_empty() {
-                    @let favicon = if !page.attributes.favicon.is_empty() { &page.attributes.favicon } else { &config.default_page_attributes.favicon };
-                    link rel="icon" href=(favicon);
-                }
-                (generate_meta_tags(&page.attributes, &config.default_page_attributes))
-                // Include global scripts first
-                @for script in &config.default_page_attributes.scripts {
-                    script type="module" src=(script) {}
-                }
-                // Then include page-specific scripts
-                @for script in &page.attributes.scripts {
-                    script type="module" src=(script) {}
-                }
-                script {
-                    (PreEscaped(format!("// Inject environment variables\nwindow.ENV = {};", generate_env_object(&config.env))))
-                }
-                @if matches!(env, Some(Env::Development)) {
-                    script {
-                        (PreEscaped(generate_live_reload_script()))
-                    }
-                }
-            }
-            (PreEscaped(body_content))
-        }
+    let source = parse_source_page(&content, input_file)?;
+    for root in [&source.head, &source.body] {
+        include::expand_includes(&templates, root, Path::new(input_file), &mut dependencies)
+            .wrap_err_with(|| format!("Failed to expand includes in {input_file}"))?;
+    }
+
+    // Expanded before the layout takes the page's slot templates, so the ones inside
+    // component elements stay with their component
+    let mut components = Components::new(input_file);
+    components
+        .expand(&templates, &source.body, &mut dependencies)
+        .wrap_err_with(|| format!("Failed to expand components in {input_file}"))?;
+
+    // Every file of a pattern page shares its configured title and description
+    if page.pattern.is_some()
+        && metadata::apply_content_fallbacks(
+            &mut attributes,
+            &metadata.front_matter.attributes,
+            &source.body,
+        )
+    {
+        templates.set_attributes(&attributes);
+    }
+
+    // Only the page's own headings, the layouts around it can list them
+    let toc = toc::build(config, &source.body);
+    templates.set_toc(&toc);
+    let content = page.pattern.is_some().then(|| {
+        toc::insert(&source.body, &toc);
+        dom::inner_html(&source.body)
+    });
+
+    let source = match layout {
+        Some(layout) => layout::apply_layout(
+            source,
+            &Path::new("src").join(layout),
+            input_file,
+            &templates,
+            &mut components,
+            &mut dependencies,
+        )
+        .wrap_err_with(|| format!("Failed to apply layout to {input_file}"))?,
+        None => source,
+    };
+    components.insert_styles(&source.head);
+    toc::insert(&source.body, &toc);
+    if let Some(pagination) = input.pagination {
+        insert_pagination_links(&source.head, pagination);
+    }
+    if let Some(links) = feed::alternate_links(config, page_name, page) {
+        dom::append_markup(&source.head, &links.into_string());
+    }
+    if noindex {
+        dom::append_markup(&source.head, &sitemap::noindex_meta().into_string());
+    }
+    let highlighted = highlight::highlight_page(config, &source, input_file)?;
+    dependencies.extend(templates.used_data());
+
+    Ok(Some(ProcessedPage {
+        html: render_source_page(config, &attributes, source, env, input_file),
+        dependencies,
+        content,
+        sitemap: (!noindex && !draft).then(|| SitemapUrl {
+            url: input.url.clone(),
+            lastmod,
+            settings: sitemap_settings,
+        }),
+        highlighted,
+    }))
+}
+
+/// Sets up the templates of one page and renders the page's attributes with them.
+fn page_templates(
+    config: &Config,
+    attributes: &ResolvedAttributes,
+    env: Option<&Env>,
+    page: PageMeta,
+    record: Option<&Value>,
+    pagination: Option<&Value>,
+) -> Result<(Templates, ResolvedAttributes)> {
+    let page_name = page.name;
+    let context = template::Context {
+        attributes,
+        env: &config.env,
+        env_name: env.copied().unwrap_or(Env::Development).name(),
+        base_url: &config.base_url,
+        page,
+        record,
+        pagination,
     };
+    let mut templates = Templates::new(config, &context);
 
-    Ok(markup.into_string())
+    let attributes = templates.render_attributes(page_name, attributes)?;
+    templates.set_attributes(&attributes);
+    Ok((templates, attributes))
 }
 
-fn extract_body_content(html: &str) -> Result<String> {
-    let html = html.trim();
-
-    if let Some(head_start) = html.find("<head") {
-        if let Some(head_end) = html.find("</head>") {
-            warn!(
-                "Found <head> section in HTML file, removing it as it will be replaced with custom head"
-            );
-            let before_head = &html[..head_start];
-            let after_head = &html[head_end + 7..];
-            let html = format!("{}{}", before_head, after_head);
-            return extract_body_from_clean_html(&html);
+/// Adds `<link rel="prev">` and `<link rel="next">` for the neighbouring listing pages.
+fn insert_pagination_links(head: &NodeRef, pagination: &Value) {
+    for (rel, field) in [("prev", "previous"), ("next", "next")] {
+        let Some(url) = pagination
+            .get_attr(field)
+            .ok()
+            .and_then(|url| url.as_str().map(str::to_string))
+        else {
+            continue;
+        };
+        dom::append_markup(head, &html! { link rel=(rel) href=(url); }.into_string());
+    }
+}
+
+/// Returns the records of a `from_data` page.
+fn data_records(config: &Config, page: &HtmlPage, path: &str) -> Result<Vec<Value>> {
+    if page.pattern.is_some() {
+        return Err(eyre!("A page cannot have both a pattern and from_data"));
+    }
+
+    let data = config.data.file(Path::new(path)).ok_or_else(|| {
+        eyre!(
+            "No data file '{path}', data files are loaded from {}/",
+            config.data_dir
+        )
+    })?;
+    if data.kind() != ValueKind::Seq {
+        return Err(eyre!("Data file '{path}' must contain a list of records"));
+    }
+
+    Ok(data.try_iter()?.collect())
+}
+
+/// Returns the tag records of a `tags_of` page.
+fn tag_records(config: &Config, page: &HtmlPage, collection: &str) -> Result<Vec<Value>> {
+    if page.pattern.is_some() || page.from_data.is_some() {
+        return Err(eyre!(
+            "A page with tags_of cannot also have a pattern or from_data"
+        ));
+    }
+    config.collections.tag_records(collection)
+}
+
+/// A record's own `title`, `description` and `author` fields win over the configured ones.
+fn apply_record_attributes(attributes: &mut ResolvedAttributes, record: &Value) {
+    for (field, attribute) in [
+        ("title", &mut attributes.title),
+        ("description", &mut attributes.description),
+        ("author", &mut attributes.author),
+    ] {
+        if let Some(value) = record_field(record, field) {
+            *attribute = value;
         }
     }
+}
 
-    extract_body_from_clean_html(html)
+/// Returns a record field as text, if it is set.
+fn record_field(record: &Value, field: &str) -> Option<String> {
+    let value = record.get_attr(field).ok()?;
+    if value.is_undefined() || value.is_none() {
+        return None;
+    }
+    Some(
+        value
+            .as_str()
+            .map_or_else(|| value.to_string(), str::to_string),
+    )
 }
 
-fn extract_body_from_clean_html(html: &str) -> Result<String> {
-    if let Some(body_start) = html.find("<body") {
-        if let Some(body_content_start) = html[body_start..].find('>') {
-            let body_start_pos = body_start + body_content_start + 1;
+/// Renders a parsed page, merging its `<head>` elements into the generated head in order.
+pub fn render_source_page(
+    config: &Config,
+    attributes: &ResolvedAttributes,
+    source: SourcePage,
+    env: Option<&Env>,
+    input_file: &str,
+) -> String {
+    let mut head_content = head::render_head(config, attributes, env);
+    for layout_head in &source.layout_heads {
+        head_content = head::merge_source_head(config, head_content, layout_head, true, input_file);
+    }
+    let head_content =
+        head::merge_source_head(config, head_content, &source.head, false, input_file);
 
-            if let Some(body_end) = html.rfind("</body>") {
-                let body_content = html[body_start_pos..body_end].trim();
-                return Ok(format!("<body>{}</body>", body_content));
-            } else {
-                warn!("No closing </body> tag found, assuming rest of content is body");
-                let body_content = html[body_start_pos..].trim();
-                return Ok(format!("<body>{}</body>", body_content));
-            }
+    let markup = html! {
+        head {
+            (head_content)
         }
+        (PreEscaped(source.body.to_string()))
+    };
+
+    let html_attributes = html_element_attributes(attributes, Some(&source.html));
+    render_document(&html_attributes, markup)
+}
+
+/// The parts of a source page that end up in the generated document.
+pub struct SourcePage {
+    pub html: NodeRef,
+    pub head: NodeRef,
+    /// Heads of the layouts the page is rendered into, outermost first
+    pub layout_heads: Vec<NodeRef>,
+    pub body: NodeRef,
+}
+
+/// Parses a source page, keeping its `<head>` and its `<body>` with attributes and comments.
+///
+/// Parse errors are reported as warnings with the line they occurred on; the parser recovers
+/// from them the same way a browser would.
+pub fn parse_source_page(html: &str, input_file: &str) -> Result<SourcePage> {
+    let (document, errors) = dom::parse_document(html);
+    for error in errors {
+        warn!("{}: {}", input_file, error);
     }
 
-    warn!("No <body> tag found, treating entire content as body");
-    Ok(format!("<body>{}</body>", html.trim()))
+    let html = dom::select_first(&document, "html")
+        .ok_or_else(|| eyre!("Parsed document has no <html>: {input_file}"))?;
+    let head = dom::select_first(&document, "head")
+        .ok_or_else(|| eyre!("Parsed document has no <head>: {input_file}"))?;
+    let body = dom::select_first(&document, "body")
+        .ok_or_else(|| eyre!("Parsed document has no <body>: {input_file}"))?;
+    Ok(SourcePage {
+        html,
+        head,
+        layout_heads: Vec::new(),
+        body,
+    })
 }
@@ -0,0 +1,267 @@
+use crate::dom;
+use crate::prelude::*;
+use crate::template::Templates;
+use color_eyre::eyre::{Context, Result, eyre};
+use kuchikiki::NodeRef;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Element replaced with the partial named by its `src` attribute.
+const INCLUDE_ELEMENT: &str = "bageri-include";
+
+/// An include directive found in a page or partial.
+struct Include {
+    src: String,
+    params: BTreeMap<String, String>,
+}
+
+/// Expands `<bageri-include src="...">` elements and `<!--#include file="..." -->` comments
+/// below `root`, recursively.
+///
+/// Paths are relative to the file containing the directive. Partials are rendered like
+/// components: as templates with the page's context and any other attributes of the directive
+/// as `props`, or with only `{{ props.name }}` filled in when the page is not templated. Every
+/// partial read is added to `dependencies`. Only the comment form works inside `<head>`, since
+/// the parser moves unknown elements into the body.
+pub fn expand_includes(
+    templates: &Templates,
+    root: &NodeRef,
+    source_file: &Path,
+    dependencies: &mut BTreeSet<PathBuf>,
+) -> Result<()> {
+    let source_file = source_file
+        .canonicalize()
+        .wrap_err_with(|| format!("Failed to resolve path: {}", source_file.display()))?;
+    let mut stack = vec![source_file.clone()];
+    expand(templates, root, &source_file, dependencies, &mut stack)
+}
+
+fn expand(
+    templates: &Templates,
+    root: &NodeRef,
+    source_file: &Path,
+    dependencies: &mut BTreeSet<PathBuf>,
+    stack: &mut Vec<PathBuf>,
+) -> Result<()> {
+    // Collect first, the tree is modified while expanding. Anything inside an include element
+    // is replaced along with it.
+    let directives: Vec<(NodeRef, Include)> = root
+        .descendants()
+        .filter(|node| {
+            !node
+                .ancestors()
+                .any(|ancestor| dom::element_name(&ancestor) == Some(INCLUDE_ELEMENT))
+        })
+        .filter_map(|node| parse_directive(&node).map(|include| (node, include)))
+        .collect();
+
+    for (node, include) in directives {
+        let path = source_file
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&include.src);
+        let path = path.canonicalize().wrap_err_with(|| {
+            format!(
+                "Failed to resolve include '{}' in {}",
+                include.src,
+                source_file.display()
+            )
+        })?;
+
+        if stack.contains(&path) {
+            let chain: Vec<String> = stack
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(eyre!("Include cycle: {}", chain.join(" -> ")));
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read partial: {}", path.display()))?;
+        dependencies.insert(path.clone());
+
+        let content =
+            templates.render_with_props(&path.display().to_string(), &content, &include.params)?;
+        let (fragment, errors) = dom::parse_fragment_at(&node, &content);
+        for error in errors {
+            warn!("{}: {}", path.display(), error);
+        }
+
+        stack.push(path.clone());
+        expand(templates, &fragment, &path, dependencies, stack)?;
+        stack.pop();
+
+        dom::replace_with_children(&node, &fragment);
+    }
+
+    Ok(())
+}
+
+fn parse_directive(node: &NodeRef) -> Option<Include> {
+    if dom::element_name(node) == Some(INCLUDE_ELEMENT) {
+        let element = node.as_element()?;
+        let mut params: BTreeMap<String, String> = element
+            .attributes
+            .borrow()
+            .map
+            .iter()
+            .map(|(name, attribute)| (name.local.to_string(), attribute.value.clone()))
+            .collect();
+        let src = params.remove("src")?;
+        return Some(Include { src, params });
+    }
+
+    let comment = node.as_comment()?.borrow();
+    let directive = comment.trim().strip_prefix("#include")?;
+    let mut params = parse_comment_attributes(directive);
+    let src = ["file", "virtual", "src"]
+        .iter()
+        .find_map(|key| params.remove(*key))?;
+    Some(Include { src, params })
+}
+
+/// Parses `name="value"` pairs from an SSI-style comment directive.
+fn parse_comment_attributes(directive: &str) -> BTreeMap<String, String> {
+    let mut attributes = BTreeMap::new();
+    let mut rest = directive;
+
+    while let Some((name, after_name)) = rest.split_once('=') {
+        let after_name = after_name.trim_start();
+        let Some(quote) = after_name
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+        else {
+            break;
+        };
+        let Some((value, after_value)) = after_name[1..].split_once(quote) else {
+            break;
+        };
+        attributes.insert(name.trim().to_string(), value.to_string());
+        rest = after_value;
+    }
+
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ResolvedAttributes};
+    use crate::html;
+    use crate::template::{self, PageMeta};
+    use minijinja::Value;
+
+    fn attributes(directive: &str) -> Vec<(String, String)> {
+        parse_comment_attributes(directive).into_iter().collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_double_and_single_quoted_values() {
+        assert_eq!(
+            attributes(r#" file="nav.html" active='about' "#),
+            pairs(&[("active", "about"), ("file", "nav.html")])
+        );
+    }
+
+    #[test]
+    fn values_keep_the_other_quote_and_equals_signs() {
+        assert_eq!(
+            attributes(r#" file = "a.html"  title="It's here" query='a="b"&c=d'"#),
+            pairs(&[
+                ("file", "a.html"),
+                ("query", r#"a="b"&c=d"#),
+                ("title", "It's here"),
+            ])
+        );
+        assert_eq!(
+            attributes(r#" file="" alt="a > b""#),
+            pairs(&[("alt", "a > b"), ("file", "")])
+        );
+    }
+
+    #[test]
+    fn stops_at_an_unquoted_or_unclosed_value() {
+        assert_eq!(
+            attributes(r#" file="a.html" size=large title="x""#),
+            pairs(&[("file", "a.html")])
+        );
+        assert_eq!(attributes(r#" file="a.html"#), pairs(&[]));
+        assert_eq!(attributes(""), pairs(&[]));
+    }
+
+    /// Expands a partial using a client-side expression into a page, with templating on or off.
+    fn expand_partial(templating: bool) -> String {
+        let dir = std::env::temp_dir().join(format!(
+            "bageri-include-{}-{templating}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("page.html"), "").unwrap();
+        std::fs::write(
+            dir.join("nav.html"),
+            "<nav>{{ props.active }} - {{ client_side }}</nav>",
+        )
+        .unwrap();
+
+        let config = Config::default();
+        let context = template::Context {
+            attributes: &ResolvedAttributes::default(),
+            env: &BTreeMap::new(),
+            env_name: "development",
+            base_url: "/",
+            page: PageMeta {
+                name: "page",
+                source: "page.html",
+                group: None,
+                url: "/page.html".to_string(),
+                meta: Value::from(()),
+                previous: None,
+                next: None,
+            },
+            record: None,
+            pagination: None,
+        };
+        let mut templates = Templates::new(&config, &context);
+        templates.set_templating(templating);
+
+        let page = html::parse_source_page(
+            r#"<main><!--#include file="nav.html" active="about" --><p>{{ vue }}</p></main>"#,
+            "page.html",
+        )
+        .unwrap();
+        let result = expand_includes(
+            &templates,
+            &page.body,
+            &dir.join("page.html"),
+            &mut BTreeSet::new(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        dom::inner_html(&page.body)
+    }
+
+    #[test]
+    fn partials_of_pages_without_templating_keep_client_side_expressions() {
+        assert_eq!(
+            expand_partial(false),
+            "<main><nav>about - {{ client_side }}</nav><p>{{ vue }}</p></main>"
+        );
+    }
+
+    #[test]
+    fn partials_of_templated_pages_are_rendered() {
+        assert_eq!(
+            expand_partial(true),
+            "<main><nav>about - </nav><p>{{ vue }}</p></main>"
+        );
+    }
+}
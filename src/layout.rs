@@ -0,0 +1,210 @@
+use crate::component::Components;
+use crate::dom;
+use crate::html::{self, SourcePage};
+use crate::include;
+use crate::prelude::*;
+use crate::template::Templates;
+use color_eyre::eyre::{Result, eyre};
+use indexmap::IndexMap;
+use kuchikiki::NodeRef;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Element in a layout naming the layout it is rendered into, relative to the layout file.
+const PARENT_ELEMENT: &str = "bageri-layout";
+
+/// Slot filled with the page content that is not inside a `<template slot="...">` block.
+/// `<slot>` without a name is the same slot.
+const DEFAULT_SLOT: &str = "main";
+
+/// Renders a page into a layout, and that layout into its own layout until one has none.
+///
+/// `<slot name="...">` elements in the layout are replaced with the page's matching
+/// `<template slot="...">` blocks, or keep their own content as a fallback. The layout's
+/// `<head>` is merged before the page's and the page's `<html>` and `<body>` attributes win.
+/// Slots a layout does not have are passed on to the layout it is rendered into.
+///
+/// Layouts of a templated page are evaluated with the page's context before they are parsed,
+/// and their components are expanded before their slots are filled. Every layout, partial and
+/// component read is added to `dependencies`.
+pub fn apply_layout(
+    page: SourcePage,
+    layout: &Path,
+    input_file: &str,
+    templates: &Templates,
+    components: &mut Components,
+    dependencies: &mut BTreeSet<PathBuf>,
+) -> Result<SourcePage> {
+    let mut page = page;
+    let mut fills = take_slot_fills(&page.body);
+    let mut layout = resolve(layout, input_file)?;
+    let mut stack: Vec<PathBuf> = Vec::new();
+
+    loop {
+        if stack.contains(&layout) {
+            let chain: Vec<String> = stack
+                .iter()
+                .chain(std::iter::once(&layout))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(eyre!("Layout cycle: {}", chain.join(" -> ")));
+        }
+        stack.push(layout.clone());
+
+        let content = std::fs::read_to_string(&layout)
+            .wrap_err_with(|| format!("Failed to read layout: {}", layout.display()))?;
+        dependencies.insert(layout.clone());
+
+        let layout_name = layout.display().to_string();
+        let content = templates.render(&layout_name, &content)?;
+        let source = html::parse_source_page(&content, &layout_name)?;
+        for root in [&source.head, &source.body] {
+            include::expand_includes(templates, root, &layout, dependencies)
+                .wrap_err_with(|| format!("Failed to expand includes in {layout_name}"))?;
+        }
+
+        let parent = take_parent(&source, &layout)?;
+        components
+            .expand(templates, &source.body, dependencies)
+            .wrap_err_with(|| format!("Failed to expand components in {layout_name}"))?;
+        let unused = fill_slots(&source.body, fills);
+
+        merge_element_attributes(&source.html, &page.html);
+        merge_element_attributes(&source.body, &page.body);
+        let composed = SourcePage {
+            html: source.html,
+            head: page.head,
+            layout_heads: std::iter::once(source.head)
+                .chain(page.layout_heads)
+                .collect(),
+            body: source.body,
+        };
+
+        match parent {
+            Some(parent) => {
+                fills = take_slot_fills(&composed.body);
+                for (name, content) in unused {
+                    fills.entry(name).or_default().push_str(&content);
+                }
+                page = composed;
+                layout = parent;
+            }
+            None => {
+                for name in unused.keys() {
+                    warn!(
+                        "{}: layout {} has no slot named '{}'",
+                        input_file, layout_name, name
+                    );
+                }
+                return Ok(composed);
+            }
+        }
+    }
+}
+
+fn resolve(path: &Path, referrer: &str) -> Result<PathBuf> {
+    path.canonicalize().wrap_err_with(|| {
+        format!(
+            "Failed to resolve layout '{}' of {referrer}",
+            path.display()
+        )
+    })
+}
+
+/// Removes the `<template slot="...">` blocks below `container` and returns their contents by
+/// slot name. Everything else in `container` fills the default slot.
+pub fn take_slot_fills(container: &NodeRef) -> IndexMap<String, String> {
+    let mut fills: IndexMap<String, String> = IndexMap::new();
+
+    let templates: Vec<NodeRef> = container
+        .descendants()
+        .filter(|node| dom::element_name(node) == Some("template"))
+        .filter(|node| dom::attribute(node, "slot").is_some())
+        .collect();
+    for template in templates {
+        // Nested templates were already taken out along with their parent
+        if !template.ancestors().any(|ancestor| ancestor == *container) {
+            continue;
+        }
+        let name = dom::attribute(&template, "slot").unwrap_or_default();
+        fills
+            .entry(name)
+            .or_default()
+            .push_str(&dom::inner_html(&template));
+        template.detach();
+    }
+
+    let rest = dom::inner_html(container);
+    if !rest.trim().is_empty() {
+        fills
+            .entry(DEFAULT_SLOT.to_string())
+            .or_default()
+            .push_str(&rest);
+    }
+    fills
+}
+
+/// Replaces the `<slot>` elements below `root` with the matching fills, or with their own
+/// content when nothing fills them. Returns the fills no slot asked for.
+pub fn fill_slots(root: &NodeRef, fills: IndexMap<String, String>) -> IndexMap<String, String> {
+    let slots: Vec<NodeRef> = root
+        .descendants()
+        .filter(|node| dom::element_name(node) == Some("slot"))
+        .collect();
+
+    let mut used = BTreeSet::new();
+    for slot in slots {
+        let name = dom::attribute(&slot, "name").unwrap_or_else(|| DEFAULT_SLOT.to_string());
+        match fills.get(&name) {
+            Some(content) => {
+                dom::replace_with_markup(&slot, content);
+                used.insert(name);
+            }
+            None => dom::replace_with_children(&slot, &slot),
+        }
+    }
+
+    fills
+        .into_iter()
+        .filter(|(name, _)| !used.contains(name))
+        .collect()
+}
+
+/// Removes the `<bageri-layout src="...">` element from a layout and resolves its path.
+fn take_parent(source: &SourcePage, layout: &Path) -> Result<Option<PathBuf>> {
+    let Some(element) = [&source.head, &source.body]
+        .into_iter()
+        .find_map(|root| dom::select_first(root, PARENT_ELEMENT))
+    else {
+        return Ok(None);
+    };
+    element.detach();
+
+    let src = dom::attribute(&element, "src").ok_or_else(|| {
+        eyre!(
+            "<{PARENT_ELEMENT}> in {} needs a src attribute",
+            layout.display()
+        )
+    })?;
+    let parent = layout.parent().unwrap_or_else(|| Path::new("")).join(src);
+    resolve(&parent, &layout.display().to_string()).map(Some)
+}
+
+/// Copies the attributes of `source` onto `target`. Classes are combined, any other attribute
+/// of `source` wins.
+fn merge_element_attributes(target: &NodeRef, source: &NodeRef) {
+    let (Some(target), Some(source)) = (target.as_element(), source.as_element()) else {
+        return;
+    };
+
+    let mut attributes = target.attributes.borrow_mut();
+    for (name, attribute) in source.attributes.borrow().map.iter() {
+        let value = match attributes.get(name.local.clone()) {
+            Some(existing) if &*name.local == "class" => {
+                dom::union_classes(existing, &attribute.value)
+            }
+            _ => attribute.value.clone(),
+        };
+        attributes.insert(name.local.clone(), value);
+    }
+}
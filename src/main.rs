@@ -8,29 +8,47 @@ use prelude::*;
 
 use axum::{
     Router,
-    routing::get,
-    response::sse::{Event, Sse},
-    response::Response,
+    extract::Request,
     http::StatusCode,
+    middleware,
+    response::sse::{Event, Sse},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
 };
 use futures::stream::{self, Stream};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     convert::Infallible,
     io::{BufRead, BufReader},
     sync::{Arc, Mutex},
     thread,
-    time::Duration,
 };
 use tokio::sync::broadcast;
 use tower_http::services::ServeDir;
 
 mod cli;
+mod collection;
+mod component;
 mod config;
+mod data;
+mod deps;
+mod dom;
+mod feed;
+mod head;
+mod highlight;
 mod html;
+mod include;
+mod layout;
 mod log;
+mod markdown;
+mod metadata;
+mod route;
+mod sitemap;
+mod template;
+mod toc;
 mod watcher;
+mod xml;
 
 #[tokio::main]
 async fn main() {
@@ -41,7 +59,7 @@ async fn main() {
 
     match args.command {
         cli::Command::Dev(_) => dev().await,
-        cli::Command::Build(_) => build().await,
+        cli::Command::Build(command) => build(command).await,
         cli::Command::Clean(_) => clean().await,
         cli::Command::Init(_) => init().await,
     }
@@ -60,14 +78,45 @@ async fn dev() -> Result<()> {
     let reload_tx_clone = reload_tx.clone();
 
     // Run initial build for development
-    build_with_env(Some(config::Env::Development)).await?;
+    let graph = build_with_env(
+        Some(config::Env::Development),
+        &config::BuildOptions::default(),
+    )
+    .await?;
+    let graph = Arc::new(Mutex::new(graph));
+
+    // Data files are page dependencies like sources and partials
+    let mut watch_patterns = config.watch_patterns.clone();
+    if std::path::Path::new(&config.data_dir).is_dir() {
+        watch_patterns.push(config.data_dir.clone());
+    }
 
     // Start file watcher for src directory
-    let _watcher = watcher::watch_files(config.watch_patterns, move || {
-        info!("Files changed, rebuilding...");
+    let _watcher = watcher::watch_files(watch_patterns, move |changed| {
         let tx = reload_tx_clone.clone();
+        let graph = graph.clone();
         tokio::spawn(async move {
-            if let Err(e) = build_with_env(Some(config::Env::Development)).await {
+            let affected = graph.lock().unwrap().affected_pages(&changed);
+            let result = match affected {
+                // Only page sources and partials changed, rebuild just the pages using them
+                Some(pages) => {
+                    info!("Files changed, rebuilding {} page(s)...", pages.len());
+                    rebuild_pages(config::Env::Development, &pages)
+                        .await
+                        .map(|rebuilt| graph.lock().unwrap().update(&pages, rebuilt))
+                }
+                None => {
+                    info!("Files changed, rebuilding...");
+                    build_with_env(
+                        Some(config::Env::Development),
+                        &config::BuildOptions::default(),
+                    )
+                    .await
+                    .map(|rebuilt| *graph.lock().unwrap() = rebuilt)
+                }
+            };
+
+            if let Err(e) = result {
                 error!("Rebuild failed: {}", e);
             } else {
                 info!("Rebuild completed");
@@ -78,22 +127,43 @@ async fn dev() -> Result<()> {
     })
     .wrap_err("Failed to start file watcher")?;
 
-    let app = Router::new()
-        .route(
-            "/",
-            get(|| async { axum::response::Redirect::permanent("/index.html") }),
-        )
-        .route("/live-reload", get({
+    let site = Arc::new(DevSite {
+        output_dir: config.output_dir.clone(),
+        base_url: config.base_url.clone(),
+        pretty_urls: config.pretty_urls,
+    });
+    let site = Router::new()
+        .fallback_service(ServeDir::new(&config.output_dir))
+        .layer(middleware::map_request(move |req| {
+            resolve_page_request(site.clone(), req)
+        }));
+
+    let mut app = Router::new().route(
+        "/live-reload",
+        get({
             let tx = reload_tx.clone();
             move || live_reload_sse(tx)
-        }))
-        .fallback_service(ServeDir::new(&config.output_dir));
+        }),
+    );
+
+    // The output is mounted under the same public path it is deployed to
+    if config.base_url != "/" {
+        let base_url = config.base_url.clone();
+        app = app.route(
+            "/",
+            get(move || async move { Redirect::temporary(&base_url) }),
+        );
+    }
+    let app = app.fallback_service(site);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
         .wrap_err("Failed to bind to address")?;
 
-    info!("Dev server running on http://127.0.0.1:3000");
+    info!(
+        "Dev server running on http://127.0.0.1:3000{}",
+        config.base_url
+    );
     info!("Serving files from: {}", config.output_dir);
 
     axum::serve(listener, app).await.wrap_err("Server error")?;
@@ -101,10 +171,62 @@ async fn dev() -> Result<()> {
     Ok(())
 }
 
+/// What the dev server needs to map request paths onto the build output.
+struct DevSite {
+    output_dir: String,
+    base_url: String,
+    pretty_urls: bool,
+}
+
+/// Maps a request under `base_url` onto the file the build wrote for it.
+///
+/// The base URL is stripped so `ServeDir` sees paths relative to `output_dir`. `/about` is
+/// served from `about.html`, unless pretty URLs are enabled and `about/index.html` exists, in
+/// which case it redirects to `/about/` like a static host would.
+async fn resolve_page_request(
+    site: Arc<DevSite>,
+    mut req: Request,
+) -> std::result::Result<Request, Response> {
+    let path = req.uri().path().to_string();
+    let inner = match path.strip_prefix(site.base_url.trim_end_matches('/')) {
+        Some("") => return Err(Redirect::temporary(&site.base_url).into_response()),
+        Some(inner) if inner.starts_with('/') => inner,
+        _ => return Err(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    let mut target = inner.to_string();
+    let trimmed = inner.trim_end_matches('/');
+    if !trimmed.is_empty()
+        && !trimmed.contains("..")
+        && std::path::Path::new(trimmed).extension().is_none()
+    {
+        let base = std::path::Path::new(&site.output_dir).join(trimmed.trim_start_matches('/'));
+        let has_dir_index = tokio::fs::metadata(base.join("index.html")).await.is_ok();
+        let has_html_file = tokio::fs::metadata(base.with_extension("html"))
+            .await
+            .is_ok();
+
+        if has_html_file && !(site.pretty_urls && has_dir_index) {
+            target = format!("{trimmed}.html");
+        } else if has_dir_index && !inner.ends_with('/') {
+            return Err(Redirect::temporary(&format!("{path}/")).into_response());
+        }
+    }
+
+    if let Some(query) = req.uri().query() {
+        target = format!("{target}?{query}");
+    }
+    if let Ok(uri) = target.parse() {
+        *req.uri_mut() = uri;
+    }
+
+    Ok(req)
+}
+
 async fn live_reload_sse(
     tx: broadcast::Sender<()>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    let mut rx = tx.subscribe();
+    let rx = tx.subscribe();
 
     let stream = stream::unfold(rx, |mut rx| async move {
         match rx.recv().await {
@@ -119,19 +241,23 @@ async fn live_reload_sse(
     Sse::new(stream)
 }
 
-async fn build() -> Result<()> {
-    build_with_env(Some(config::Env::Production)).await
+async fn build(command: cli::BuildCommand) -> Result<()> {
+    let options = config::BuildOptions {
+        include_drafts: command.include_drafts,
+        now: command.now,
+    };
+    build_with_env(Some(config::Env::Production), &options).await?;
+    Ok(())
 }
 
-async fn build_with_env(env: Option<config::Env>) -> Result<()> {
-    let env_name = match env {
-        Some(config::Env::Production) => "production",
-        Some(config::Env::Development) => "development",
-        None => "default",
-    };
+async fn build_with_env(
+    env: Option<config::Env>,
+    options: &config::BuildOptions,
+) -> Result<deps::DependencyGraph> {
+    let env_name = env.map_or("default", config::Env::name);
     info!("Building for {}...", env_name);
 
-    let config = config::Config::load(env)
+    let mut config = config::Config::load_with(env, options)
         .await
         .wrap_err("Failed to load configuration")?;
 
@@ -228,25 +354,90 @@ async fn build_with_env(env: Option<config::Env>) -> Result<()> {
         info!("All pre-build hooks completed successfully");
     }
 
+    // After the hooks, which may generate data files
+    config.load_sources().await?;
+
     // Use the passed environment parameter
     let current_env = env.unwrap_or(config::Env::Development);
+    let graph = render_pages(&config, current_env, None).await?;
+
+    if let Some(timestamp) = config::source_date_epoch() {
+        clamp_output_mtimes(std::path::Path::new(&config.output_dir), timestamp)?;
+        info!("Clamped output modification times to SOURCE_DATE_EPOCH");
+    }
+
+    info!(
+        "Build complete! Static files are in the {} directory.",
+        config.output_dir
+    );
+    Ok(graph)
+}
+
+/// Rebuilds only the given HTML page sources, skipping pre-build hooks and SPA pages.
+async fn rebuild_pages(
+    env: config::Env,
+    pages: &BTreeSet<deps::PageSource>,
+) -> Result<deps::DependencyGraph> {
+    let mut config = config::Config::load(Some(env))
+        .await
+        .wrap_err("Failed to load configuration")?;
+    config.load_sources().await?;
+
+    render_pages(&config, env, Some(pages)).await
+}
+
+/// Writes every page, or only the HTML page sources in `only`, and records what each HTML
+/// page read.
+async fn render_pages(
+    config: &config::Config,
+    current_env: config::Env,
+    only: Option<&BTreeSet<deps::PageSource>>,
+) -> Result<deps::DependencyGraph> {
+    let mut graph = deps::DependencyGraph::default();
+
+    // Every published page that is not `noindex`, for the sitemap
+    let mut sitemap_urls = Vec::new();
 
     // Generate HTML files for each SPA page
-    for (page_name, page) in &config.spa_pages {
-        let html_content = html::generate_html(&config, page, Some(&current_env));
-        let html_filename = if page_name == "index" {
-            format!("{}/index.html", config.output_dir)
+    for (page_name, page) in config.spa_pages.iter().filter(|_| only.is_none()) {
+        let page_route = route::page_route(page_name, page.route.as_deref())
+            .wrap_err_with(|| format!("Invalid route for page: {page_name}"))?;
+        let page_route = if config.pretty_urls {
+            route::prettify(&page_route)
         } else {
-            format!("{}/{}.html", config.output_dir, page_name)
+            page_route
         };
+        let url = head::route_url(config, &page_route);
+        let mut dependencies = BTreeSet::new();
+        let html_content = html::generate_html(
+            config,
+            page_name,
+            page,
+            &url,
+            Some(&current_env),
+            &mut dependencies,
+        )
+        .wrap_err_with(|| format!("Failed to generate page: {page_name}"))?;
+        graph.record_site_wide(dependencies);
+        let html_filename = route::output_path(&config.output_dir, &page_route);
+        if !page.sitemap.noindex.unwrap_or(false) {
+            sitemap_urls.push(sitemap::SitemapUrl {
+                url,
+                lastmod: None,
+                settings: page.sitemap.clone(),
+            });
+        }
 
-        tokio::fs::write(&html_filename, html_content)
-            .await
-            .wrap_err_with(|| format!("Failed to write HTML file: {html_filename}"))?;
+        write_output_file(&html_filename, html_content).await?;
 
-        info!("Generated HTML file: {}", html_filename);
+        info!("Generated HTML file: {}", html_filename.display());
     }
 
+    // Rendered content of every pattern page source, for the feeds
+    let mut contents = BTreeMap::new();
+    // Whether any page links the highlight stylesheet
+    let mut highlighted = false;
+
     // Process HTML pages
     for (page_name, page) in &config.html_pages {
         let input_files = html::find_html_files(page_name, page)
@@ -254,43 +445,124 @@ async fn build_with_env(env: Option<config::Env>) -> Result<()> {
             .wrap_err_with(|| format!("Failed to find HTML files for page: {}", page_name))?;
 
         for input_file in input_files {
-            let html_content = html::process_html_page(&config, page, &input_file, Some(&current_env))
-                .await
-                .wrap_err_with(|| format!("Failed to process HTML file: {}", input_file))?;
-
-            // Determine output filename
-            let output_filename = if page.pattern.is_some() {
-                // For pattern-based files, use the original filename but in output dir
-                let input_path = std::path::Path::new(&input_file);
-                let filename = input_path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("output.html");
-                format!("{}/{}", config.output_dir, filename)
-            } else {
-                // For non-pattern files, use page name
-                if page_name == "index" {
-                    format!("{}/index.html", config.output_dir)
-                } else {
-                    format!("{}/{}.html", config.output_dir, page_name)
-                }
+            let source = deps::PageSource {
+                page_name: page_name.clone(),
+                input_file: input_file.clone(),
             };
+            if only.is_some_and(|only| !only.contains(&source)) {
+                continue;
+            }
 
-            tokio::fs::write(&output_filename, html_content)
-                .await
-                .wrap_err_with(|| {
-                    format!("Failed to write processed HTML file: {}", output_filename)
-                })?;
+            let outputs = html::page_outputs(config, page_name, page, &input_file)
+                .wrap_err_with(|| format!("Invalid page: {page_name}"))?;
+            if let Some(path) = &page.from_data {
+                graph.record(&source, [std::path::PathBuf::from(path)]);
+            }
+            if let Some(collection) = page.listed_collection() {
+                graph.record(&source, config.collections.files(collection)?);
+            }
+            for output in &outputs {
+                let output_filename = route::output_path(&config.output_dir, &output.route);
+
+                let input = html::PageInput {
+                    page_name,
+                    page,
+                    input_file: &input_file,
+                    url: head::route_url(config, &output.route),
+                    record: output.record.as_ref(),
+                    pagination: output.pagination.as_ref(),
+                };
+                let Some(processed) = html::process_html_page(config, &input, Some(&current_env))
+                    .await
+                    .wrap_err_with(|| format!("Failed to process HTML file: {}", input_file))?
+                else {
+                    info!("Skipped unpublished page: {}", input_file);
+                    continue;
+                };
+                graph.record(&source, processed.dependencies);
+                if let Some(content) = processed.content {
+                    contents.insert(input_file.clone(), content);
+                }
+                sitemap_urls.extend(processed.sitemap);
+                highlighted |= processed.highlighted;
+
+                write_output_file(&output_filename, processed.html).await?;
 
-            info!("Processed HTML file: {} -> {}", input_file, output_filename);
+                info!(
+                    "Processed HTML file: {} -> {}",
+                    input_file,
+                    output_filename.display()
+                );
+            }
         }
     }
 
-    info!(
-        "Build complete! Static files are in the {} directory.",
-        config.output_dir
-    );
-    Ok(())
+    if highlighted {
+        let path = std::path::Path::new(&config.output_dir).join(&config.highlight.stylesheet);
+        let css =
+            highlight::stylesheet(config).wrap_err("Failed to generate highlight stylesheet")?;
+        write_output_file(&path, css).await?;
+    }
+
+    // Feeds embed every entry's content, so they are only written when all pages are
+    for feed in config.feeds.iter().filter(|_| only.is_none()) {
+        let xml = feed::render(config, feed, &contents)
+            .wrap_err_with(|| format!("Failed to generate feed: {}", feed.path))?;
+        let path = std::path::Path::new(&config.output_dir).join(&feed.path);
+        write_output_file(&path, xml).await?;
+
+        info!("Generated feed: {}", path.display());
+    }
+
+    // Like the feeds, the sitemap lists every page
+    if only.is_none() && sitemap::is_written(config) {
+        for (path, xml) in
+            sitemap::render(config, &sitemap_urls).wrap_err("Failed to generate sitemap")?
+        {
+            let path = std::path::Path::new(&config.output_dir).join(path);
+            write_output_file(&path, xml).await?;
+
+            info!("Generated sitemap: {}", path.display());
+        }
+    }
+    if only.is_none() && config.robots.is_some() {
+        let path = std::path::Path::new(&config.output_dir).join("robots.txt");
+        let text = sitemap::robots_txt(config).wrap_err("Failed to generate robots.txt")?;
+        write_output_file(&path, text).await?;
+
+        info!("Generated robots.txt: {}", path.display());
+    }
+
+    Ok(graph)
+}
+
+/// Writes a generated file, creating any nested directories its route needs.
+async fn write_output_file(path: &std::path::Path, content: String) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .wrap_err_with(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    tokio::fs::write(path, content)
+        .await
+        .wrap_err_with(|| format!("Failed to write file: {}", path.display()))
+}
+
+/// Sets the mtime of everything in the output directory, so archives of it hash identically.
+fn clamp_output_mtimes(path: &std::path::Path, timestamp: std::time::SystemTime) -> Result<()> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)
+            .wrap_err_with(|| format!("Failed to read directory: {}", path.display()))?
+        {
+            let entry = entry.wrap_err("Failed to read directory entry")?;
+            clamp_output_mtimes(&entry.path(), timestamp)?;
+        }
+    }
+
+    std::fs::File::open(path)
+        .and_then(|file| file.set_modified(timestamp))
+        .wrap_err_with(|| format!("Failed to set modification time: {}", path.display()))
 }
 
 fn spawn_output_reader<R: std::io::Read + Send + 'static>(
@@ -0,0 +1,25 @@
+/// Returns true for the source files bageri renders as Markdown.
+pub fn is_markdown(path: &str) -> bool {
+    path.ends_with(".md")
+}
+
+/// Converts Markdown to HTML with GitHub flavored tables, task lists, strikethrough, autolinks
+/// and footnotes. Front matter is split off by [`crate::metadata::read`] before.
+///
+/// Raw HTML in the Markdown is kept, so includes and components work as in HTML pages.
+pub fn to_html(source: &str) -> String {
+    comrak::markdown_to_html(source, &options())
+}
+
+fn options() -> comrak::Options<'static> {
+    let mut options = comrak::Options::default();
+    options.extension.table = true;
+    options.extension.tasklist = true;
+    options.extension.footnotes = true;
+    options.extension.strikethrough = true;
+    options.extension.autolink = true;
+    options.render.unsafe_ = true;
+    // Keeps what follows the language in a fence, e.g. line numbers to highlight
+    options.render.full_info_string = true;
+    options
+}
@@ -0,0 +1,337 @@
+use crate::config::{Config, HtmlPage, PageAttributes, ResolvedAttributes, SitemapSettings};
+use crate::data;
+use crate::dom;
+use color_eyre::eyre::{Context, Result};
+use kuchikiki::NodeRef;
+use minijinja::Value;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Extensions of the sidecar file next to a source, e.g. `post.json5` for `post.html`.
+const SIDECAR_EXTENSIONS: [&str; 5] = ["json5", "json", "yaml", "yml", "toml"];
+
+/// Fields of `<meta>` tags that also set the page's attributes. Meta tags only hold text, so
+/// typed fields like `draft` or `scripts` are left to front matter and sidecars.
+const META_TAG_ATTRIBUTES: [&str; 3] = ["title", "description", "author"];
+
+/// Longest description taken from the first paragraph, in characters.
+const FALLBACK_DESCRIPTION_LENGTH: usize = 160;
+
+/// What a source file says about itself.
+pub struct SourceMetadata {
+    pub front_matter: FrontMatter,
+    /// Every declared field, including ones bageri does not use itself
+    pub meta: Value,
+}
+
+/// Declared fields that change how the page is built.
+#[derive(Debug, Default, Deserialize)]
+pub struct FrontMatter {
+    /// Layout the page is rendered into, instead of the configured one
+    #[serde(default)]
+    pub layout: Option<String>,
+    /// Only built in development, or with `bageri build --include-drafts`
+    #[serde(default)]
+    pub draft: Option<bool>,
+    /// `noindex`, `priority` and `changefreq` on top of the page's
+    #[serde(flatten)]
+    pub sitemap: SitemapSettings,
+    /// `title`, `description`, `author`, `scripts`, `styles`, ... on top of the configured ones
+    #[serde(flatten)]
+    pub attributes: PageAttributes,
+}
+
+/// Reads the metadata of a source file and returns it with the source minus its front matter.
+///
+/// Fields come from, in increasing precedence: the `<title>` and `<meta name="...">` tags in
+/// an HTML source's `<head>`, a sidecar file with the same stem (`post.json5`, `post.yaml`,
+/// `post.toml`, ...), and a YAML (`---`) or TOML (`+++`) front matter block at the top of the
+/// source. Of the meta tags, only the title, description and author set page attributes; the
+/// rest are only available as `page.meta`. The sidecar, if any, is added to `dependencies`.
+pub fn read(
+    source: &str,
+    input_file: &str,
+    is_markdown: bool,
+    dependencies: &mut BTreeSet<PathBuf>,
+) -> Result<(SourceMetadata, String)> {
+    let (front_matter, body) = match split_front_matter(source) {
+        Some((delimiter, text, body)) if !text.trim().is_empty() => (
+            Some(
+                parse_front_matter::<Value>(delimiter, text)
+                    .wrap_err_with(|| format!("Invalid front matter in {input_file}"))?,
+            ),
+            body,
+        ),
+        Some((_, _, body)) => (None, body),
+        None => (None, source),
+    };
+
+    let head = if is_markdown {
+        BTreeMap::new()
+    } else {
+        head_fields(body)
+    };
+    let mut declared: BTreeMap<String, Value> = head
+        .iter()
+        .filter(|(name, _)| META_TAG_ATTRIBUTES.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    if let Some(sidecar) = read_sidecar(input_file, dependencies)? {
+        declared.extend(map_entries(&sidecar));
+    }
+    if let Some(front_matter) = &front_matter {
+        declared.extend(map_entries(front_matter));
+    }
+
+    let front_matter = FrontMatter::deserialize(Value::from_serialize(&declared))
+        .wrap_err_with(|| format!("Invalid metadata in {input_file}"))?;
+    let mut fields = head;
+    fields.extend(declared);
+    let meta = Value::from_serialize(&fields);
+    Ok((SourceMetadata { front_matter, meta }, body.to_string()))
+}
+
+/// Returns true if the build publishes a page. The file's own `draft` and `publish_date` win
+/// over the configured ones, and a record's fields over both.
+pub fn is_published(
+    config: &Config,
+    page: &HtmlPage,
+    metadata: &SourceMetadata,
+    record: Option<&Value>,
+) -> Result<bool> {
+    let draft = is_draft(page, metadata, record);
+    let publish_date = record
+        .and_then(|record| date_field(record, "publish_date"))
+        .or_else(|| date_field(&metadata.meta, "publish_date"))
+        .or_else(|| page.publish_date.clone());
+
+    config
+        .publishing
+        .is_published(draft, publish_date.as_deref())
+}
+
+/// Returns true if a page is a draft, with the same precedence as [`is_published`].
+pub fn is_draft(page: &HtmlPage, metadata: &SourceMetadata, record: Option<&Value>) -> bool {
+    record
+        .and_then(|record| record.get_attr("draft").ok())
+        .filter(|draft| !draft.is_undefined() && !draft.is_none())
+        .map(|draft| draft.is_true())
+        .or(metadata.front_matter.draft)
+        .or(page.draft)
+        .unwrap_or(false)
+}
+
+/// Fills in the title and description a pattern page's file does not declare from its first
+/// `<h1>` and first paragraph. Returns true if anything changed.
+pub fn apply_content_fallbacks(
+    attributes: &mut ResolvedAttributes,
+    declared: &PageAttributes,
+    body: &NodeRef,
+) -> bool {
+    let (title, description) = content_fallbacks(body);
+    let mut changed = false;
+    if declared.title.is_none()
+        && let Some(title) = title
+    {
+        attributes.title = title;
+        changed = true;
+    }
+    if declared.description.is_none()
+        && let Some(description) = description
+    {
+        attributes.description = description;
+        changed = true;
+    }
+    changed
+}
+
+/// The text of the first `<h1>` and of the first paragraph, shortened to a description.
+pub fn content_fallbacks(body: &NodeRef) -> (Option<String>, Option<String>) {
+    (
+        first_text(body, "h1"),
+        first_text(body, "p").map(|text| truncate(&text, FALLBACK_DESCRIPTION_LENGTH)),
+    )
+}
+
+/// Returns a date field as text. TOML dates are converted back to how they were written.
+pub fn date_field(meta: &Value, field: &str) -> Option<String> {
+    let value = meta.get_attr(field).ok()?;
+    if let Some(date) = value.as_str() {
+        return Some(date.to_string());
+    }
+    // `toml` keeps its dates in a single-field map so they survive serialization
+    let key = value.try_iter().ok()?.next()?;
+    value.get_item(&key).ok()?.as_str().map(str::to_string)
+}
+
+/// The text of the first non-empty `element` below `root`, with its whitespace collapsed.
+fn first_text(root: &NodeRef, element: &str) -> Option<String> {
+    root.descendants()
+        .filter(|node| dom::element_name(node) == Some(element))
+        .map(|node| {
+            node.text_contents()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .find(|text| !text.is_empty())
+}
+
+/// Cuts `text` at the last word that fits in `length` characters.
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(length).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(words, _)| words);
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_ascii_punctuation())
+    )
+}
+
+/// `title` from `<title>` and every `<meta name="..." content="...">` in the `<head>`.
+fn head_fields(source: &str) -> BTreeMap<String, Value> {
+    // Parse errors are reported when the rendered page is parsed
+    let (document, _) = dom::parse_document(source);
+    let Some(head) = dom::select_first(&document, "head") else {
+        return BTreeMap::new();
+    };
+
+    let mut fields = BTreeMap::new();
+    for node in head.children() {
+        match dom::element_name(&node) {
+            Some("title") => {
+                let title = node.text_contents().trim().to_string();
+                if !title.is_empty() {
+                    fields.insert("title".to_string(), Value::from(title));
+                }
+            }
+            Some("meta") => {
+                if let (Some(name), Some(content)) = (
+                    dom::attribute(&node, "name"),
+                    dom::attribute(&node, "content"),
+                ) {
+                    fields.insert(name, Value::from(content));
+                }
+            }
+            _ => {}
+        }
+    }
+    fields
+}
+
+/// Reads the first sidecar file found next to `input_file`.
+fn read_sidecar(input_file: &str, dependencies: &mut BTreeSet<PathBuf>) -> Result<Option<Value>> {
+    for extension in SIDECAR_EXTENSIONS {
+        let path = Path::new(input_file).with_extension(extension);
+        if !path.is_file() {
+            continue;
+        }
+        dependencies.insert(path.clone());
+        return data::load_file(&path);
+    }
+    Ok(None)
+}
+
+fn map_entries(value: &Value) -> Vec<(String, Value)> {
+    let Ok(keys) = value.try_iter() else {
+        return Vec::new();
+    };
+    keys.filter_map(|key| {
+        let field = value.get_item(&key).ok()?;
+        Some((key.as_str()?.to_string(), field))
+    })
+    .collect()
+}
+
+fn parse_front_matter<T: DeserializeOwned>(delimiter: &str, text: &str) -> Result<T> {
+    Ok(match delimiter {
+        "---" => serde_yaml::from_str(text)?,
+        _ => toml::from_str(text)?,
+    })
+}
+
+/// Returns the delimiter, the front matter and the rest of the source, if the source starts
+/// with a front matter block.
+fn split_front_matter(source: &str) -> Option<(&'static str, &str, &str)> {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+
+    for delimiter in ["---", "+++"] {
+        let Some(rest) = source.strip_prefix(delimiter) else {
+            continue;
+        };
+        let Some(rest) = rest
+            .strip_prefix("\r\n")
+            .or_else(|| rest.strip_prefix('\n'))
+        else {
+            continue;
+        };
+
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == delimiter {
+                return Some((delimiter, &rest[..offset], &rest[offset + line.len()..]));
+            }
+            offset += line.len();
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_yaml_and_toml_front_matter() {
+        assert_eq!(
+            split_front_matter("---\ntitle: A\n---\n# Body\n"),
+            Some(("---", "title: A\n", "# Body\n"))
+        );
+        assert_eq!(
+            split_front_matter("+++\ntitle = \"A\"\n+++\n# Body\n"),
+            Some(("+++", "title = \"A\"\n", "# Body\n"))
+        );
+    }
+
+    #[test]
+    fn accepts_crlf_a_byte_order_mark_and_a_fence_at_the_end() {
+        assert_eq!(
+            split_front_matter("---\r\ntitle: A\r\n---\r\nBody"),
+            Some(("---", "title: A\r\n", "Body"))
+        );
+        assert_eq!(
+            split_front_matter("\u{feff}---\ntitle: A\n---\nBody"),
+            Some(("---", "title: A\n", "Body"))
+        );
+        assert_eq!(
+            split_front_matter("---\ntitle: A\n---"),
+            Some(("---", "title: A\n", ""))
+        );
+        assert_eq!(
+            split_front_matter("---\n---\nBody"),
+            Some(("---", "", "Body"))
+        );
+    }
+
+    #[test]
+    fn needs_a_closing_fence_of_the_same_kind() {
+        assert_eq!(split_front_matter("---\ntitle: A\n# Body\n"), None);
+        assert_eq!(split_front_matter("+++\ntitle = \"A\"\n"), None);
+        assert_eq!(split_front_matter("---\ntitle: A\n+++\nBody"), None);
+        assert_eq!(split_front_matter("+++\ntitle = \"A\"\n---\nBody"), None);
+    }
+
+    #[test]
+    fn only_matches_a_fence_on_the_first_line() {
+        assert_eq!(split_front_matter("\n---\ntitle: A\n---\n"), None);
+        assert_eq!(split_front_matter("---title: A\n---\n"), None);
+        assert_eq!(split_front_matter("# Title\n---\nBody"), None);
+    }
+}
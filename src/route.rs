@@ -0,0 +1,330 @@
+use color_eyre::eyre::{Result, eyre};
+use std::path::{Component, Path, PathBuf};
+
+/// Placeholder in a pattern page route that is replaced with the source file stem.
+const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Returns the URL path for a page that maps to a single output file.
+///
+/// An explicit `route` wins; otherwise the page key is used, with `index` mapping to `/`.
+pub fn page_route(page_name: &str, route: Option<&str>) -> Result<String> {
+    match route {
+        Some(route) => normalize(route),
+        None if page_name == "index" => Ok("/".to_string()),
+        None => normalize(&format!("{page_name}.html")),
+    }
+}
+
+/// Returns the URL path for one file matched by a pattern page.
+///
+/// Without a `route` the source filename is kept, with Markdown sources becoming `.html`. A
+/// route ending in `/` is treated as the directory the matched files land in, and `{name}` is
+/// replaced with the source file stem.
+pub fn pattern_route(route: Option<&str>, input_file: &str) -> Result<String> {
+    let input_path = Path::new(input_file);
+    let source_name = input_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| eyre!("Invalid source file name: {input_file}"))?;
+    let stem = input_path
+        .file_stem()
+        .and_then(|n| n.to_str())
+        .unwrap_or(source_name);
+    let file_name = &if input_path.extension().is_some_and(|ext| ext == "md") {
+        format!("{stem}.html")
+    } else {
+        source_name.to_string()
+    };
+
+    match route {
+        None => normalize(file_name),
+        Some(route) if route.contains(NAME_PLACEHOLDER) => {
+            normalize(&route.replace(NAME_PLACEHOLDER, stem))
+        }
+        Some(route) if route.ends_with('/') => normalize(&format!("{route}{file_name}")),
+        Some(route) => Err(eyre!(
+            "Route '{route}' of a pattern page must end with '/' or contain {NAME_PLACEHOLDER}"
+        )),
+    }
+}
+
+/// Returns the URL path for one record of a `from_data` page.
+///
+/// Every `{field}` in the route is replaced with that field of the record, e.g.
+/// `/products/{slug}/`.
+pub fn record_route(route: Option<&str>, field: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let route = route.ok_or_else(|| eyre!("Pages generated from data need a route"))?;
+    if !route.contains('{') {
+        return Err(eyre!(
+            "Route '{route}' must contain a {{field}} placeholder, or every record is written \
+             to the same file"
+        ));
+    }
+
+    let mut resolved = String::with_capacity(route.len());
+    let mut rest = route;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| eyre!("Unclosed placeholder in route '{route}'"))?;
+        let name = &rest[start + 1..start + end];
+        let value = field(name)
+            .ok_or_else(|| eyre!("Record has no field '{name}' used in route '{route}'"))?;
+        resolved.push_str(&rest[..start]);
+        resolved.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+
+    normalize(&resolved)
+}
+
+/// Rewrites a page route into a directory route, e.g. `/about.html` -> `/about/`.
+///
+/// `index.html` collapses into its parent directory. Directory routes and routes to other
+/// file types are returned unchanged.
+pub fn prettify(route: &str) -> String {
+    if route.ends_with('/') {
+        return route.to_string();
+    }
+
+    let stem = match route.strip_suffix(".html") {
+        Some(stem) => stem,
+        None if Path::new(route).extension().is_none() => route,
+        None => return route.to_string(),
+    };
+
+    match stem.strip_suffix("index") {
+        Some(dir) if dir.ends_with('/') => dir.to_string(),
+        _ => format!("{stem}/"),
+    }
+}
+
+/// Returns the route of page `number` of a paginated page. The first page keeps the route,
+/// later ones go below it: `/blog/` becomes `/blog/page/2/` and `/blog.html` becomes
+/// `/blog/page/2.html`.
+pub fn numbered_route(route: &str, number: usize) -> Result<String> {
+    if number <= 1 {
+        return Ok(route.to_string());
+    }
+    if route.ends_with('/') {
+        return normalize(&format!("{route}page/{number}/"));
+    }
+
+    let stem = route.strip_suffix(".html").unwrap_or(route);
+    let dir = match stem.strip_suffix("index") {
+        Some(dir) if dir.ends_with('/') => dir.to_string(),
+        _ => format!("{stem}/"),
+    };
+    normalize(&format!("{dir}page/{number}.html"))
+}
+
+/// Maps a URL path to the file it is written to inside `output_dir`.
+///
+/// Routes ending in `/` produce `index.html` inside that directory, and routes without an
+/// extension get `.html` appended.
+pub fn output_path(output_dir: &str, route: &str) -> PathBuf {
+    let relative = route.trim_start_matches('/');
+    let mut path = PathBuf::from(output_dir);
+
+    if relative.is_empty() || relative.ends_with('/') {
+        path.push(relative);
+        path.push("index.html");
+    } else if Path::new(relative).extension().is_none() {
+        path.push(format!("{relative}.html"));
+    } else {
+        path.push(relative);
+    }
+
+    path
+}
+
+/// Ensures a route starts with `/` and cannot escape the output directory.
+fn normalize(route: &str) -> Result<String> {
+    let route = route.trim();
+    let route = if route.starts_with('/') {
+        route.to_string()
+    } else {
+        format!("/{route}")
+    };
+
+    let escapes = Path::new(route.trim_start_matches('/'))
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+    if escapes {
+        return Err(eyre!(
+            "Route '{route}' must stay inside the output directory"
+        ));
+    }
+
+    Ok(route)
+}
+
+/// Lowercases the text and joins its words with `-`, dropping punctuation.
+pub fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_')
+            && !slug.is_empty()
+            && !slug.ends_with('-')
+        {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_routes_default_to_the_page_key() {
+        assert_eq!(page_route("index", None).unwrap(), "/");
+        assert_eq!(page_route("about", None).unwrap(), "/about.html");
+        assert_eq!(
+            page_route("intro", Some("docs/guide/intro.html")).unwrap(),
+            "/docs/guide/intro.html"
+        );
+        assert_eq!(page_route("docs", Some(" /docs/ ")).unwrap(), "/docs/");
+    }
+
+    #[test]
+    fn routes_cannot_escape_the_output_directory() {
+        assert!(page_route("up", Some("../up.html")).is_err());
+        assert!(page_route("up", Some("/docs/../../up.html")).is_err());
+        assert!(pattern_route(Some("/blog/../../"), "src/hello.html").is_err());
+    }
+
+    #[test]
+    fn pattern_routes_keep_the_file_name_below_the_route() {
+        assert_eq!(
+            pattern_route(None, "src/blog/hello.md").unwrap(),
+            "/hello.html"
+        );
+        assert_eq!(
+            pattern_route(Some("/blog/2025/"), "src/blog/hello.html").unwrap(),
+            "/blog/2025/hello.html"
+        );
+        assert_eq!(
+            pattern_route(Some("/blog/{name}/"), "src/blog/hello.md").unwrap(),
+            "/blog/hello/"
+        );
+        assert!(pattern_route(Some("/blog"), "src/blog/hello.md").is_err());
+    }
+
+    #[test]
+    fn nested_routes_map_to_nested_output_files() {
+        let output = |route| output_path("dist", route);
+        assert_eq!(output("/"), Path::new("dist/index.html"));
+        assert_eq!(
+            output("/docs/guide/"),
+            Path::new("dist/docs/guide/index.html")
+        );
+        assert_eq!(
+            output("/docs/guide/intro"),
+            Path::new("dist/docs/guide/intro.html")
+        );
+        assert_eq!(
+            output("/docs/guide/intro.html"),
+            Path::new("dist/docs/guide/intro.html")
+        );
+        assert_eq!(output("/feed.xml"), Path::new("dist/feed.xml"));
+    }
+
+    #[test]
+    fn pretty_routes_are_directories() {
+        assert_eq!(prettify("/about.html"), "/about/");
+        assert_eq!(prettify("/about"), "/about/");
+        assert_eq!(prettify("/docs/index.html"), "/docs/");
+        assert_eq!(prettify("/index.html"), "/");
+        assert_eq!(prettify("/reindex.html"), "/reindex/");
+        assert_eq!(prettify("/docs/"), "/docs/");
+        assert_eq!(prettify("/feed.xml"), "/feed.xml");
+    }
+
+    #[test]
+    fn pretty_and_html_routes_write_the_same_page_to_different_files() {
+        for (route, html, pretty) in [
+            ("/about.html", "dist/about.html", "dist/about/index.html"),
+            (
+                "/docs/guide/intro.html",
+                "dist/docs/guide/intro.html",
+                "dist/docs/guide/intro/index.html",
+            ),
+            (
+                "/docs/index.html",
+                "dist/docs/index.html",
+                "dist/docs/index.html",
+            ),
+        ] {
+            assert_eq!(output_path("dist", route), Path::new(html));
+            assert_eq!(output_path("dist", &prettify(route)), Path::new(pretty));
+        }
+    }
+
+    #[test]
+    fn record_routes_fill_in_fields() {
+        let field = |name: &str| match name {
+            "category" => Some("bread".to_string()),
+            "slug" => Some("rye".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            record_route(Some("/shop/{category}/{slug}/"), field).unwrap(),
+            "/shop/bread/rye/"
+        );
+        assert_eq!(
+            record_route(Some("{slug}.html"), field).unwrap(),
+            "/rye.html"
+        );
+        assert!(record_route(None, field).is_err());
+        assert!(record_route(Some("/shop/"), field).is_err());
+        assert!(record_route(Some("/shop/{slug"), field).is_err());
+        assert!(record_route(Some("/shop/{price}/"), field).is_err());
+    }
+
+    #[test]
+    fn the_first_page_keeps_its_route() {
+        for route in ["/blog/", "/blog.html", "/"] {
+            assert_eq!(numbered_route(route, 0).unwrap(), route);
+            assert_eq!(numbered_route(route, 1).unwrap(), route);
+        }
+    }
+
+    #[test]
+    fn later_pages_go_below_the_first_with_html_urls() {
+        let page = |route, number| numbered_route(route, number).unwrap();
+        assert_eq!(page("/blog.html", 2), "/blog/page/2.html");
+        assert_eq!(page("/blog/index.html", 3), "/blog/page/3.html");
+        assert_eq!(page("/index.html", 2), "/page/2.html");
+        assert_eq!(page("/news/archive.html", 12), "/news/archive/page/12.html");
+        assert_eq!(
+            output_path("dist", &page("/blog.html", 2)),
+            Path::new("dist/blog/page/2.html")
+        );
+    }
+
+    #[test]
+    fn later_pages_go_below_the_first_with_pretty_urls() {
+        // Pagination prettifies the numbered routes of the unprettified first route
+        let page = |route, number| prettify(&numbered_route(route, number).unwrap());
+        assert_eq!(page("/blog.html", 1), "/blog/");
+        assert_eq!(page("/blog.html", 2), "/blog/page/2/");
+        assert_eq!(page("/blog/index.html", 3), "/blog/page/3/");
+        assert_eq!(page("/blog/", 2), "/blog/page/2/");
+        assert_eq!(page("/index.html", 2), "/page/2/");
+        assert_eq!(
+            output_path("dist", &page("/blog.html", 2)),
+            Path::new("dist/blog/page/2/index.html")
+        );
+    }
+}
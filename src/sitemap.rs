@@ -0,0 +1,143 @@
+use crate::config::{Config, SitemapSettings};
+use crate::head;
+use crate::xml::escape;
+use color_eyre::eyre::{Result, eyre};
+use maud::{Markup, html};
+use std::fmt::Write;
+
+/// Most URLs one sitemap file may list.
+const MAX_URLS: usize = 50_000;
+
+/// One published page of the sitemap.
+pub struct SitemapUrl {
+    /// Public URL of the page, including `base_url`
+    pub url: String,
+    /// `YYYY-MM-DD`, or a full timestamp
+    pub lastmod: Option<String>,
+    pub settings: SitemapSettings,
+}
+
+/// Returns the sitemap's files by path in the output directory.
+///
+/// Up to 50 000 pages are listed in `sitemap.path` itself. Larger sites are split into
+/// `sitemap-1.xml`, `sitemap-2.xml`, ... next to it, with `sitemap.path` as their index.
+pub fn render(config: &Config, urls: &[SitemapUrl]) -> Result<Vec<(String, String)>> {
+    let site_url = site_url(config)?;
+    if urls.len() <= MAX_URLS {
+        return Ok(vec![(config.sitemap.path.clone(), urlset(site_url, urls)?)]);
+    }
+
+    let (directory, _) = config.sitemap.path.rsplit_once('/').unwrap_or(("", ""));
+    let mut files = Vec::new();
+    let mut index = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        index,
+        r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for (number, chunk) in urls.chunks(MAX_URLS).enumerate() {
+        let path = match directory {
+            "" => format!("sitemap-{}.xml", number + 1),
+            directory => format!("{directory}/sitemap-{}.xml", number + 1),
+        };
+        let location = format!("{site_url}{}", head::route_url(config, &path));
+        writeln!(
+            index,
+            "  <sitemap><loc>{}</loc></sitemap>",
+            escape(&location)
+        )?;
+        files.push((path, urlset(site_url, chunk)?));
+    }
+    writeln!(index, "</sitemapindex>")?;
+    files.push((config.sitemap.path.clone(), index));
+
+    Ok(files)
+}
+
+/// `robots.txt` with the configured rules, pointing crawlers at the sitemap if one is written.
+pub fn robots_txt(config: &Config) -> Result<String> {
+    let mut text = String::new();
+    let rules = config
+        .robots
+        .as_ref()
+        .map(|robots| robots.rules.as_slice())
+        .unwrap_or_default();
+    if rules.is_empty() {
+        writeln!(text, "User-agent: *")?;
+        writeln!(text, "Allow: /")?;
+    }
+    for (index, rule) in rules.iter().enumerate() {
+        if index > 0 {
+            writeln!(text)?;
+        }
+        writeln!(text, "User-agent: {}", rule.user_agent)?;
+        for path in &rule.allow {
+            writeln!(text, "Allow: {}", head::route_url(config, path))?;
+        }
+        for path in &rule.disallow {
+            writeln!(text, "Disallow: {}", head::route_url(config, path))?;
+        }
+        if rule.allow.is_empty() && rule.disallow.is_empty() {
+            writeln!(text, "Disallow:")?;
+        }
+    }
+
+    if is_written(config) {
+        let site_url = site_url(config)?;
+        let location = head::route_url(config, &config.sitemap.path);
+        writeln!(text)?;
+        writeln!(text, "Sitemap: {site_url}{location}")?;
+    }
+    Ok(text)
+}
+
+/// Returns true if the build writes a sitemap.
+pub fn is_written(config: &Config) -> bool {
+    config.sitemap.enabled && config.site_url.is_some()
+}
+
+/// `<meta name="robots">` for pages left out of the sitemap.
+pub fn noindex_meta() -> Markup {
+    html! { meta name="robots" content="noindex"; }
+}
+
+fn urlset(site_url: &str, urls: &[SitemapUrl]) -> Result<String> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#
+    )?;
+    for url in urls {
+        writeln!(xml, "  <url>")?;
+        writeln!(
+            xml,
+            "    <loc>{}</loc>",
+            escape(&format!("{site_url}{}", url.url))
+        )?;
+        if let Some(lastmod) = &url.lastmod {
+            writeln!(xml, "    <lastmod>{}</lastmod>", escape(lastmod))?;
+        }
+        if let Some(changefreq) = url.settings.changefreq {
+            writeln!(xml, "    <changefreq>{}</changefreq>", changefreq.as_str())?;
+        }
+        if let Some(priority) = url.settings.priority {
+            if !(0.0..=1.0).contains(&priority) {
+                return Err(eyre!(
+                    "Sitemap priority of {} must be between 0.0 and 1.0, not {priority}",
+                    url.url
+                ));
+            }
+            writeln!(xml, "    <priority>{priority}</priority>")?;
+        }
+        writeln!(xml, "  </url>")?;
+    }
+    writeln!(xml, "</urlset>")?;
+    Ok(xml)
+}
+
+fn site_url(config: &Config) -> Result<&str> {
+    config
+        .site_url
+        .as_deref()
+        .map(|site_url| site_url.trim_end_matches('/'))
+        .ok_or_else(|| eyre!("The sitemap needs a site_url, e.g. \"https://example.com\""))
+}
@@ -0,0 +1,221 @@
+use crate::collection::CollectionItem;
+use crate::config::{Config, ResolvedAttributes};
+use crate::data::UsedData;
+use crate::head;
+use crate::toc::TocEntry;
+use color_eyre::eyre::{Context as _, Result};
+use minijinja::{AutoEscape, Environment, HtmlEscape, Value};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// Values available to page and layout templates, next to `data` and `collections`.
+#[derive(Debug, Serialize)]
+pub struct Context<'a> {
+    /// The resolved title, description, scripts, styles, ... of the page
+    pub attributes: &'a ResolvedAttributes,
+    /// Variables loaded from the env files, including `BASE_URL`
+    pub env: &'a BTreeMap<String, String>,
+    /// `development` or `production`
+    pub env_name: &'static str,
+    pub base_url: &'a str,
+    pub page: PageMeta<'a>,
+    /// The record a `from_data` page is rendered for
+    pub record: Option<&'a Value>,
+    /// The page of a paginated collection listing
+    pub pagination: Option<&'a Value>,
+}
+
+/// Where a page comes from and where it is published.
+#[derive(Debug, Serialize)]
+pub struct PageMeta<'a> {
+    /// Key of the page in the config
+    pub name: &'a str,
+    /// Source file, empty for SPA pages
+    pub source: &'a str,
+    pub group: Option<&'a str>,
+    /// Public URL of the page, including `base_url`
+    pub url: String,
+    /// Metadata the source file declares itself, e.g. Markdown front matter
+    pub meta: Value,
+    /// The item of the page's collection published before this one
+    pub previous: Option<&'a CollectionItem>,
+    /// The item of the page's collection published after this one
+    pub next: Option<&'a CollectionItem>,
+}
+
+/// Renders Jinja-style templates (`{{ value | filter }}`, `{% for %}`, `{% if %}`) against one
+/// page's context.
+pub struct Templates {
+    environment: Environment<'static>,
+    context: Value,
+    used_data: UsedData,
+    /// Whether the page's sources, layouts, partials and components are evaluated
+    templating: bool,
+}
+
+impl Templates {
+    pub fn new(config: &Config, context: &Context) -> Self {
+        let mut environment = Environment::new();
+        // Every template renders into HTML, whatever its file is called
+        environment.set_auto_escape_callback(|_| AutoEscape::Html);
+
+        let base_url = config.base_url.clone();
+        environment.add_filter("asset_url", move |url: String| {
+            head::prefix_base_url(&base_url, &url)
+        });
+
+        let used_data = UsedData::default();
+        let data = config.data.tracked(used_data.clone());
+        let collections = config.collections.tracked(used_data.clone());
+        Self {
+            environment,
+            context: minijinja::context! {
+                data => data,
+                collections => collections,
+                ..Value::from_serialize(context)
+            },
+            used_data,
+            templating: true,
+        }
+    }
+
+    /// Renders the configured attributes that contain template syntax, e.g.
+    /// `title: "{{ data.site.name }}"`. Attributes cannot refer to each other.
+    pub fn render_attributes(
+        &self,
+        page_name: &str,
+        attributes: &ResolvedAttributes,
+    ) -> Result<ResolvedAttributes> {
+        let render = |field: &str, value: &str| -> Result<String> {
+            if !value.contains("{{") && !value.contains("{%") {
+                return Ok(value.to_string());
+            }
+            // Escaped when the attribute is written into the document, not here
+            let source = format!("{{% autoescape false %}}{value}{{% endautoescape %}}");
+            self.evaluate(&format!("{page_name}.{field}"), &source, &self.context)
+        };
+        let render_all = |field: &str, values: &[String]| -> Result<Vec<String>> {
+            values.iter().map(|value| render(field, value)).collect()
+        };
+
+        Ok(ResolvedAttributes {
+            title: render("title", &attributes.title)?,
+            favicon: render("favicon", &attributes.favicon)?,
+            author: render("author", &attributes.author)?,
+            description: render("description", &attributes.description)?,
+            scripts: render_all("scripts", &attributes.scripts)?,
+            styles: render_all("styles", &attributes.styles)?,
+            lang: render("lang", &attributes.lang)?,
+            dir: render("dir", &attributes.dir)?,
+            class: render("class", &attributes.class)?,
+            html_attributes: attributes
+                .html_attributes
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), render(name, value)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Replaces the attributes templates see, after [`Templates::render_attributes`].
+    pub fn set_attributes(&mut self, attributes: &ResolvedAttributes) {
+        self.context = minijinja::context! { attributes => attributes, ..self.context.clone() };
+    }
+
+    /// Makes the page's table of contents available as `toc`, to the layouts rendered after it.
+    pub fn set_toc(&mut self, toc: &[TocEntry]) {
+        self.context = minijinja::context! { toc => toc, ..self.context.clone() };
+    }
+
+    /// Turns evaluating the page's markup on or off, following [`HtmlPage::is_templated`].
+    ///
+    /// When off, layouts and partials are used as written and only `{{ props.name }}` is
+    /// filled in, so markup meant for client-side frameworks passes through.
+    ///
+    /// [`HtmlPage::is_templated`]: crate::config::HtmlPage::is_templated
+    pub fn set_templating(&mut self, templating: bool) {
+        self.templating = templating;
+    }
+
+    /// The data files read by everything rendered so far.
+    pub fn used_data(&self) -> BTreeSet<PathBuf> {
+        self.used_data.lock().unwrap().clone()
+    }
+
+    /// Renders `source`, naming it `name` in error messages.
+    pub fn render(&self, name: &str, source: &str) -> Result<String> {
+        if !self.templating {
+            return Ok(source.to_string());
+        }
+        self.evaluate(name, source, &self.context)
+    }
+
+    /// Renders `source` with `props` available next to the page's context.
+    pub fn render_with_props(
+        &self,
+        name: &str,
+        source: &str,
+        props: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        if !self.templating {
+            return Ok(substitute_props(source, props));
+        }
+        let context = minijinja::context! { props => props, ..self.context.clone() };
+        self.evaluate(name, source, &context)
+    }
+
+    fn evaluate(&self, name: &str, source: &str, context: &Value) -> Result<String> {
+        self.environment
+            .render_named_str(name, source, context)
+            .wrap_err_with(|| format!("Failed to render template: {name}"))
+    }
+}
+
+/// Replaces every `{{ props.name }}` with the escaped prop, or nothing if it is not set, and
+/// leaves any other `{{ ... }}` as written.
+fn substitute_props(source: &str, props: &BTreeMap<String, String>) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let expression = rest[start + 2..start + length - 2].trim();
+        let prop = expression.strip_prefix("props.").filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        });
+        match prop {
+            Some(name) => {
+                if let Some(value) = props.get(name) {
+                    output.push_str(&HtmlEscape(value).to_string());
+                }
+            }
+            None => output.push_str(&rest[start..start + length]),
+        }
+        rest = &rest[start + length..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_only_props() {
+        let props = BTreeMap::from([("title".to_string(), "Fish & <chips>".to_string())]);
+        assert_eq!(
+            substitute_props(
+                "<h2>{{props.title}}</h2>{{ props.missing }}<p>{{ message }}</p>",
+                &props
+            ),
+            "<h2>Fish &amp; &lt;chips&gt;</h2><p>{{ message }}</p>"
+        );
+        assert_eq!(
+            substitute_props("{{ props.title | upper }} {{ props.title", &props),
+            "{{ props.title | upper }} {{ props.title"
+        );
+    }
+}
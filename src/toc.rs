@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::dom;
+use crate::route;
+use kuchikiki::NodeRef;
+use maud::{Markup, html};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::iter::Peekable;
+
+/// Element replaced with the table of contents of the page, in the page or its layouts.
+const TOC_ELEMENT: &str = "bageri-toc";
+
+/// A heading in the table of contents and the deeper headings that follow it.
+#[derive(Debug, Clone, Serialize)]
+pub struct TocEntry {
+    /// 1 for `<h1>` to 6 for `<h6>`
+    pub level: u8,
+    pub id: String,
+    pub text: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Gives every heading below `root` an id and returns the table of contents.
+///
+/// Ids are derived from the heading text, so they stay the same between builds as long as the
+/// text does. Headings that already have an id keep it, and repeated text gets `-1`, `-2`, ...
+/// With `headings.anchors`, a link to the heading is appended to it.
+pub fn build(config: &Config, root: &NodeRef) -> Vec<TocEntry> {
+    let headings: Vec<(u8, NodeRef)> = root
+        .descendants()
+        .filter_map(|node| Some((heading_level(&node)?, node)))
+        .collect();
+
+    let mut used: BTreeSet<String> = root
+        .descendants()
+        .filter_map(|node| dom::attribute(&node, "id"))
+        .collect();
+
+    let settings = &config.headings;
+    let mut entries = Vec::new();
+    for (level, heading) in headings {
+        let text = heading
+            .text_contents()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let id = match dom::attribute(&heading, "id") {
+            Some(id) => id,
+            None => {
+                let id = unique_id(&route::slug(&text), &used);
+                used.insert(id.clone());
+                if let Some(element) = heading.as_element() {
+                    element.attributes.borrow_mut().insert("id", id.clone());
+                }
+                id
+            }
+        };
+
+        if settings.anchors {
+            let markup = format!(
+                r##"<a class="heading-anchor" href="#{}" aria-hidden="true">{}</a>"##,
+                id, settings.anchor_text
+            );
+            dom::append_markup(&heading, &markup);
+        }
+
+        if (settings.toc_min_level..=settings.toc_max_level).contains(&level) {
+            entries.push(TocEntry {
+                level,
+                id,
+                text,
+                children: Vec::new(),
+            });
+        }
+    }
+
+    nest(&mut entries.into_iter().peekable(), 0)
+}
+
+/// Replaces the `<bageri-toc>` elements below `root` with a `<nav>` listing `toc`. The
+/// element's own attributes are kept on the `<nav>`.
+pub fn insert(root: &NodeRef, toc: &[TocEntry]) {
+    let placeholders: Vec<NodeRef> = root
+        .descendants()
+        .filter(|node| dom::element_name(node) == Some(TOC_ELEMENT))
+        .collect();
+
+    for placeholder in placeholders {
+        if toc.is_empty() {
+            placeholder.detach();
+            continue;
+        }
+
+        let markup = html! { nav { (render_entries(toc)) } }.into_string();
+        let (fragment, _) = dom::parse_fragment_at(&placeholder, &markup);
+        for nav in fragment.children() {
+            if let (Some(nav), Some(source)) = (nav.as_element(), placeholder.as_element()) {
+                let mut attributes = nav.attributes.borrow_mut();
+                for (name, attribute) in source.attributes.borrow().map.iter() {
+                    attributes.insert(name.local.clone(), attribute.value.clone());
+                }
+            }
+        }
+        dom::replace_with_children(&placeholder, &fragment);
+    }
+}
+
+fn render_entries(entries: &[TocEntry]) -> Markup {
+    html! {
+        ol {
+            @for entry in entries {
+                li {
+                    a href=(format!("#{}", entry.id)) { (entry.text) }
+                    @if !entry.children.is_empty() {
+                        (render_entries(&entry.children))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Collects the entries deeper than `level` into a tree, stopping at the first that is not.
+fn nest(entries: &mut Peekable<impl Iterator<Item = TocEntry>>, level: u8) -> Vec<TocEntry> {
+    let mut nested = Vec::new();
+    while let Some(mut entry) = entries.next_if(|entry| entry.level > level) {
+        entry.children = nest(entries, entry.level);
+        nested.push(entry);
+    }
+    nested
+}
+
+/// Returns 2 for an `<h2>` element.
+fn heading_level(node: &NodeRef) -> Option<u8> {
+    match dom::element_name(node)? {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn unique_id(slug: &str, used: &BTreeSet<String>) -> String {
+    if !used.contains(slug) {
+        return slug.to_string();
+    }
+    (1..)
+        .map(|n| format!("{slug}-{n}"))
+        .find(|id| !used.contains(id))
+        .expect("there are more suffixes than ids")
+}
@@ -1,13 +1,14 @@
 use crate::prelude::*;
 use color_eyre::eyre::{Context, Result};
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::time::Duration;
 
 pub fn watch_files(
     watch_patterns: Vec<String>,
-    callback: impl Fn() + Send + 'static,
+    callback: impl Fn(Vec<PathBuf>) + Send + 'static,
 ) -> Result<RecommendedWatcher> {
     let (tx, rx) = mpsc::channel();
 
@@ -47,13 +48,15 @@ pub fn watch_files(
 
     tokio::spawn(async move {
         let mut debounce_timer = None::<tokio::time::Instant>;
+        let mut changed_paths = BTreeSet::new();
 
         loop {
             match rx.try_recv() {
                 Ok(event) => {
                     match event {
-                        Ok(_event) => {
+                        Ok(event) => {
                             // Debounce rapid file changes
+                            changed_paths.extend(event.paths);
                             debounce_timer = Some(tokio::time::Instant::now());
                         }
                         Err(e) => {
@@ -63,11 +66,11 @@ pub fn watch_files(
                 }
                 Err(mpsc::TryRecvError::Empty) => {
                     // Check if we should trigger a rebuild
-                    if let Some(timer) = debounce_timer {
-                        if timer.elapsed() > Duration::from_millis(500) {
-                            callback();
-                            debounce_timer = None;
-                        }
+                    if let Some(timer) = debounce_timer
+                        && timer.elapsed() > Duration::from_millis(500)
+                    {
+                        callback(std::mem::take(&mut changed_paths).into_iter().collect());
+                        debounce_timer = None;
                     }
 
                     tokio::time::sleep(Duration::from_millis(100)).await;
@@ -81,4 +84,3 @@ pub fn watch_files(
 
     Ok(watcher)
 }
-
@@ -0,0 +1,7 @@
+/// Escapes text for XML content and attribute values, e.g. in feeds and sitemaps.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}